@@ -0,0 +1,111 @@
+//! Benchmarks of the read counting loop and of the parsing of the SDUST output, over synthetic
+//! in-memory data.
+//!
+//! Run with `cargo bench`; criterion compares each run to the previous one.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use freq_motif_fastq::{
+    parse_dust_output, process_reads_and_write_fasta, MotifOptions, Progress, ReadSelection,
+};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::hint::black_box;
+use std::io::{self, Write};
+
+/// Number of reads of the synthetic FASTQ
+const READS: usize = 100_000;
+/// Length of the synthetic reads
+const READ_LENGTH: usize = 150;
+
+/// Builds a FASTQ of random reads, one in five made of a repeated dinucleotide or
+/// trinucleotide for the motifs and low-complexity regions to be found
+fn synthetic_fastq() -> Vec<u8> {
+    let mut rng = StdRng::seed_from_u64(42);
+    let repeats: [&[u8]; 4] = [b"AT", b"CA", b"GGC", b"TTA"];
+    let mut fastq = Vec::new();
+    for i in 0..READS {
+        let sequence: Vec<u8> = if i % 5 == 0 {
+            let repeat = repeats[rng.gen_range(0..repeats.len())];
+            repeat.iter().copied().cycle().take(READ_LENGTH).collect()
+        } else {
+            (0..READ_LENGTH)
+                .map(|_| b"ACGT"[rng.gen_range(0..4)])
+                .collect()
+        };
+        writeln!(fastq, "@read{}", i).unwrap();
+        fastq.extend_from_slice(&sequence);
+        fastq.extend_from_slice(b"\n+\n");
+        fastq.extend(std::iter::repeat_n(b'I', READ_LENGTH));
+        fastq.push(b'\n');
+    }
+    fastq
+}
+
+/// Builds an SDUST output masking a few intervals of most of the reads
+fn synthetic_dust_output() -> String {
+    let mut rng = StdRng::seed_from_u64(42);
+    let mut output = String::new();
+    for read in 0..READS {
+        let mut start = 0;
+        for _ in 0..rng.gen_range(0..4) {
+            start += rng.gen_range(0..40);
+            let end = start + rng.gen_range(1..30);
+            output.push_str(&format!("{}\t{}\t{}\n", read, start, end));
+            start = end;
+        }
+    }
+    output
+}
+
+fn bench_process_reads(c: &mut Criterion) {
+    let fastq = synthetic_fastq();
+    let options = MotifOptions::default();
+    let mut group = c.benchmark_group("process_reads_and_write_fasta");
+    group.throughput(Throughput::Elements(READS as u64));
+    group.sample_size(10);
+
+    // The built-in DUST masks the reads when no FASTA is written for SDUST
+    group.bench_function("builtin_dust", |b| {
+        b.iter(|| {
+            process_reads_and_write_fasta(
+                black_box(fastq.as_slice()),
+                None,
+                None,
+                ReadSelection::First(usize::MAX),
+                0,
+                &Progress::Hidden,
+                &options,
+            )
+            .unwrap()
+        })
+    });
+    group.bench_function("fasta_for_sdust", |b| {
+        b.iter(|| {
+            let mut fasta = io::sink();
+            process_reads_and_write_fasta(
+                black_box(fastq.as_slice()),
+                None,
+                Some(&mut fasta),
+                ReadSelection::First(usize::MAX),
+                0,
+                &Progress::Hidden,
+                &options,
+            )
+            .unwrap()
+        })
+    });
+    group.finish();
+}
+
+fn bench_parse_dust_output(c: &mut Criterion) {
+    let dust_output = synthetic_dust_output();
+    let mut group = c.benchmark_group("parse_dust_output");
+    group.throughput(Throughput::Bytes(dust_output.len() as u64));
+    group.bench_function("synthetic", |b| {
+        b.iter(|| parse_dust_output(black_box(&dust_output), READS).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_process_reads, bench_parse_dust_output);
+criterion_main!(benches);
@@ -0,0 +1,339 @@
+//! Checkpoints of the counts of a long run, saved periodically so that a run interrupted by a
+//! crash is resumed from the last one instead of starting over.
+//!
+//! A checkpoint holds the [`ReadStats`] of the reads processed so far and the number of input
+//! records read to get them, which a resumed run skips. Gzipped inputs cannot be seeked, so
+//! records are counted rather than bytes.
+//!
+//! A checkpoint is only resumed from by a run of the same input files, unchanged in size, and
+//! of the same counting settings, which it records along with the counts.
+
+use crate::{
+    AppError, AtomicFile, Motif, MotifOptions, ReadSelection, ReadStats, AMBIGUOUS_MOTIF,
+    LOW_COMPLEXITY_MOTIF, LOW_ENTROPY_MOTIF,
+};
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Where and how often the checkpoints of a run are saved
+#[derive(Debug, Clone)]
+pub struct CheckpointOptions {
+    pub path: PathBuf,
+    /// Minimum time between two checkpoints
+    pub interval: Duration,
+    /// Input files the reads are read from, those of the second mates included (`-` for stdin)
+    pub inputs: Vec<String>,
+}
+
+/// Input file of a checkpoint, with its size for a replaced or rewritten file to be told apart
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct SavedInput {
+    pub path: String,
+    /// Size of the file in bytes, unless it is stdin
+    pub size: Option<u64>,
+}
+
+/// Input files as they are saved in a checkpoint
+fn saved_inputs(inputs: &[String]) -> Vec<SavedInput> {
+    inputs
+        .iter()
+        .map(|path| SavedInput {
+            path: path.clone(),
+            size: (path != "-")
+                .then(|| fs::metadata(path).ok().map(|metadata| metadata.len()))
+                .flatten(),
+        })
+        .collect()
+}
+
+/// Settings the counts of a run depend on, which a run resumed from its checkpoints must share
+pub(crate) fn fingerprint(options: &MotifOptions, selection: ReadSelection) -> String {
+    let mut kmer_min_proportions: Vec<_> = options.kmer_min_proportions.iter().collect();
+    kmer_min_proportions.sort_unstable_by_key(|&(&k, _)| k);
+    [
+        format!("kmer_lengths={:?}", options.kmer_lengths),
+        format!("motifs={:?}", options.motifs),
+        format!("min_proportion={:?}", options.min_proportion),
+        format!("kmer_min_proportions={:?}", kmer_min_proportions),
+        format!("canonical={:?}", options.canonical),
+        format!("both_strands={:?}", options.both_strands),
+        format!("preserve_case={:?}", options.preserve_case),
+        format!("ambiguous={:?}", options.ambiguous),
+        format!("length_bins={:?}", options.length_bins),
+        format!("gc_content={:?}", options.gc_content),
+        format!("non_overlapping={:?}", options.non_overlapping),
+        format!("min_mean_quality={:?}", options.min_mean_quality),
+        format!("phred_offset={:?}", options.phred_offset),
+        format!("input_format={:?}", options.input_format),
+        format!("trim={:?}", (options.trim_start, options.trim_end)),
+        format!("position_motifs={:?}", options.position_motifs),
+        format!("dust={:?}", (options.dust_window, options.dust_threshold)),
+        format!("homopolymers={:?}", options.homopolymers),
+        format!("dinucleotides={:?}", options.dinucleotide_composition),
+        format!("enrichment={:?}", options.enrichment),
+        format!("min_entropy={:?}", options.min_entropy),
+        format!(
+            "read_length={:?}",
+            (options.min_read_length, options.max_read_length)
+        ),
+        format!("long_reads={:?}", options.long_reads),
+        format!("metric={:?}", options.metric),
+        format!("weight={:?}", options.weight),
+        format!("aggregate_stat={:?}", options.aggregate_stat),
+        format!("alphabet={:?}", options.alphabet),
+        format!("interleaved={:?}", options.interleaved),
+        format!(
+            "name_filter={:?}",
+            options.name_filter.as_ref().map(|filter| filter.as_str())
+        ),
+        format!("by_tile={:?}", options.by_tile),
+        format!("read_names={:?}", options.read_names),
+        format!("dust_intervals={:?}", options.dust_intervals),
+        format!("masked_composition={:?}", options.masked_composition),
+        format!("selection={:?}", selection),
+    ]
+    .join(", ")
+}
+
+/// Counts of the reads processed before a checkpoint, with where they stop in the input
+#[derive(Deserialize)]
+pub struct Checkpoint {
+    /// Input files the reads were read from
+    pub inputs: Vec<SavedInput>,
+    /// Settings the counts were made with, as given by [`fingerprint`]
+    pub options: String,
+    /// Number of reads, or read pairs, skipped before the processed ones
+    pub skipped: usize,
+    /// Number of input records, or pairs, read so far, the skipped ones included
+    pub records: usize,
+    /// Number of reads, or read pairs, selected so far
+    pub selected: usize,
+    /// Number of reads checked against the filters so far
+    pub examined_reads: usize,
+    pub stats: ReadStats,
+}
+
+/// Borrowed [`Checkpoint`], to save the counts without copying them
+#[derive(Serialize)]
+struct CheckpointRef<'a> {
+    inputs: Vec<SavedInput>,
+    options: &'a str,
+    skipped: usize,
+    records: usize,
+    selected: usize,
+    examined_reads: usize,
+    stats: &'a ReadStats,
+}
+
+impl Checkpoint {
+    /// Checks that the checkpoint was saved by a run of the same inputs, reads skipped and
+    /// settings, its counts being merged with the new ones otherwise
+    pub fn check(
+        &self,
+        options: &CheckpointOptions,
+        skipped: usize,
+        fingerprint: &str,
+    ) -> Result<(), AppError> {
+        if self.inputs != saved_inputs(&options.inputs) {
+            return Err(AppError::Input(format!(
+                "The checkpoint {} was saved for other input files, or they changed since",
+                options.path.display()
+            )));
+        }
+        if self.skipped != skipped {
+            return Err(AppError::Input(format!(
+                "The checkpoint was saved skipping {} reads, not {}",
+                self.skipped, skipped
+            )));
+        }
+        if self.options != fingerprint {
+            return Err(AppError::Input(format!(
+                "The checkpoint {} was saved with other counting options",
+                options.path.display()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Loads the checkpoint of a previous run, if there is one
+pub fn load(path: &Path) -> Result<Option<Checkpoint>, AppError> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(AppError::Input(format!(
+                "Failed to open checkpoint {}: {}",
+                path.display(),
+                e
+            )))
+        }
+    };
+    serde_json::from_reader(BufReader::new(file))
+        .map(Some)
+        .map_err(|e| AppError::Input(format!("Invalid checkpoint {}: {}", path.display(), e)))
+}
+
+/// Saves the counts of the reads processed so far, replacing the previous checkpoint at once
+pub fn save(
+    options: &CheckpointOptions,
+    fingerprint: &str,
+    skipped: usize,
+    records: usize,
+    selected: usize,
+    examined_reads: usize,
+    stats: &ReadStats,
+) -> Result<(), AppError> {
+    let path = &options.path;
+    let checkpoint = CheckpointRef {
+        inputs: saved_inputs(&options.inputs),
+        options: fingerprint,
+        skipped,
+        records,
+        selected,
+        examined_reads,
+        stats,
+    };
+    AtomicFile::create(path)
+        .and_then(|mut file| {
+            serde_json::to_writer(&mut file, &checkpoint)?;
+            file.commit()
+        })
+        .map_err(|e| {
+            AppError::Output(format!(
+                "Failed to save checkpoint {}: {}",
+                path.display(),
+                e
+            ))
+        })
+}
+
+/// Counted motif as it is saved: categories are saved by name and matched back to the known
+/// ones when loaded
+#[derive(Serialize, Deserialize)]
+enum SavedMotif {
+    Packed { length: usize, bases: u64 },
+    Named(String),
+    Category(String),
+}
+
+impl From<Motif> for SavedMotif {
+    fn from(motif: Motif) -> SavedMotif {
+        match motif {
+            Motif::Packed { length, bases } => SavedMotif::Packed { length, bases },
+            Motif::Named(name) => SavedMotif::Named(name),
+            Motif::Category(name) => SavedMotif::Category(name.to_string()),
+        }
+    }
+}
+
+impl TryFrom<SavedMotif> for Motif {
+    type Error = String;
+
+    fn try_from(motif: SavedMotif) -> Result<Motif, String> {
+        match motif {
+            SavedMotif::Packed { length, bases } => Ok(Motif::Packed { length, bases }),
+            SavedMotif::Named(name) => Ok(Motif::Named(name)),
+            SavedMotif::Category(name) => {
+                [AMBIGUOUS_MOTIF, LOW_COMPLEXITY_MOTIF, LOW_ENTROPY_MOTIF]
+                    .into_iter()
+                    .find(|category| *category == name)
+                    .map(Motif::Category)
+                    .ok_or_else(|| format!("unknown category {}", name))
+            }
+        }
+    }
+}
+
+impl Serialize for Motif {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SavedMotif::from(self.clone()).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Motif {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Motif, D::Error> {
+        Motif::try_from(SavedMotif::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+}
+
+/// Saves a map whose keys are not strings, which JSON objects cannot have, as a list of
+/// key-value pairs
+pub(crate) mod pairs {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::HashMap;
+    use std::hash::Hash;
+
+    pub fn serialize<K, V, S>(map: &HashMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        K: Serialize,
+        V: Serialize,
+        S: Serializer,
+    {
+        serializer.collect_seq(map)
+    }
+
+    pub fn deserialize<'de, K, V, D>(deserializer: D) -> Result<HashMap<K, V>, D::Error>
+    where
+        K: Deserialize<'de> + Eq + Hash,
+        V: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        let pairs: Vec<(K, V)> = Vec::deserialize(deserializer)?;
+        Ok(pairs.into_iter().collect())
+    }
+}
+
+/// Saves the maps of both mates as lists of key-value pairs, like [`pairs`]
+pub(crate) mod mate_pairs {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::HashMap;
+    use std::hash::Hash;
+
+    pub fn serialize<K, V, S>(maps: &[HashMap<K, V>; 2], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        K: Serialize,
+        V: Serialize,
+        S: Serializer,
+    {
+        let [r1, r2] = maps;
+        [r1.iter().collect::<Vec<_>>(), r2.iter().collect()].serialize(serializer)
+    }
+
+    pub fn deserialize<'de, K, V, D>(deserializer: D) -> Result<[HashMap<K, V>; 2], D::Error>
+    where
+        K: Deserialize<'de> + Eq + Hash,
+        V: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        let [r1, r2]: [Vec<(K, V)>; 2] = Deserialize::deserialize(deserializer)?;
+        Ok([r1.into_iter().collect(), r2.into_iter().collect()])
+    }
+}
+
+/// Saves an array of counts longer than the 32 elements serde handles on its own
+pub(crate) mod long_array {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer, const N: usize>(
+        array: &[u64; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(array)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(
+        deserializer: D,
+    ) -> Result<[u64; N], D::Error> {
+        let counts: Vec<u64> = Vec::deserialize(deserializer)?;
+        let length = counts.len();
+        counts
+            .try_into()
+            .map_err(|_| D::Error::custom(format!("{} counts instead of {}", length, N)))
+    }
+}
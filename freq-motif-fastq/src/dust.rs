@@ -0,0 +1,206 @@
+//! Native implementation of the symmetric DUST algorithm, following the reference SDUST
+//! implementation (Morgulis et al. 2006) so that both report the same masked intervals.
+
+use std::collections::VecDeque;
+
+/// Window size used by SDUST when none is given
+pub const DEFAULT_WINDOW: usize = 64;
+/// Score threshold used by SDUST when none is given
+pub const DEFAULT_THRESHOLD: i64 = 20;
+
+/// Length of the words (triplets) scored by DUST
+const WORD_LEN: usize = 3;
+/// Number of distinct words
+const WORD_TOTAL: usize = 1 << (WORD_LEN << 1);
+/// Mask keeping the last `WORD_LEN` bases of the packed word
+const WORD_MASK: usize = WORD_TOTAL - 1;
+
+/// A perfect interval of the current window
+struct PerfectInterval {
+    start: usize,
+    finish: usize,
+    score: i64,
+    length: i64,
+}
+
+/// Sliding state of the DUST scan
+struct Dust {
+    threshold: i64,
+    window: usize,
+    /// Words of the current window
+    words: VecDeque<usize>,
+    /// Perfect intervals, sorted by descending start and then by ascending finish
+    perfect: Vec<PerfectInterval>,
+    /// Masked intervals found so far
+    masked: Vec<(usize, usize)>,
+    /// Length of the suffix of the window scoring below the threshold
+    suffix_len: usize,
+    window_score: i64,
+    suffix_score: i64,
+    window_counts: [i64; WORD_TOTAL],
+    suffix_counts: [i64; WORD_TOTAL],
+}
+
+/// Encodes a base on 2 bits, returning 4 for anything that is not A/C/G/T
+fn encode_base(base: u8) -> usize {
+    match base {
+        b'A' | b'a' => 0,
+        b'C' | b'c' => 1,
+        b'G' | b'g' => 2,
+        b'T' | b't' | b'U' | b'u' => 3,
+        _ => 4,
+    }
+}
+
+/// Finds the low-complexity intervals of a sequence.
+/// Intervals are 0-based and half-open, as reported by the `sdust` binary.
+pub fn sdust(seq: &[u8], threshold: i64, window: usize) -> Vec<(usize, usize)> {
+    let mut dust = Dust {
+        threshold,
+        window,
+        words: VecDeque::with_capacity(window),
+        perfect: Vec::new(),
+        masked: Vec::new(),
+        suffix_len: 0,
+        window_score: 0,
+        suffix_score: 0,
+        window_counts: [0; WORD_TOTAL],
+        suffix_counts: [0; WORD_TOTAL],
+    };
+
+    // `run` is the length of the current stretch of A/C/G/T bases
+    let mut run = 0;
+    let mut word = 0;
+    for i in 0..=seq.len() {
+        let base = if i < seq.len() {
+            encode_base(seq[i])
+        } else {
+            4
+        };
+
+        if base < 4 {
+            run += 1;
+            word = ((word << 2) | base) & WORD_MASK;
+            if run >= WORD_LEN {
+                // Start of the current window
+                let start = run.saturating_sub(window) + (i + 1 - run);
+                dust.save_masked_regions(start);
+                dust.shift_window(word);
+                if dust.window_score * 10 > dust.suffix_len as i64 * threshold {
+                    dust.find_perfect(start);
+                }
+            }
+        } else {
+            // An ambiguous base or the end of the sequence splits it into independent pieces
+            let mut start = (run + 1).saturating_sub(window) + (i + 1 - run);
+            while !dust.perfect.is_empty() {
+                dust.save_masked_regions(start);
+                start += 1;
+            }
+            run = 0;
+            word = 0;
+        }
+    }
+
+    dust.masked
+}
+
+impl Dust {
+    /// Adds a word to the window, dropping the oldest one once the window is full
+    fn shift_window(&mut self, word: usize) {
+        if self.words.len() + WORD_LEN > self.window {
+            if let Some(oldest) = self.words.pop_front() {
+                self.window_counts[oldest] -= 1;
+                self.window_score -= self.window_counts[oldest];
+                if self.suffix_len > self.words.len() {
+                    self.suffix_len -= 1;
+                    self.suffix_counts[oldest] -= 1;
+                    self.suffix_score -= self.suffix_counts[oldest];
+                }
+            }
+        }
+
+        self.words.push_back(word);
+        self.suffix_len += 1;
+        self.window_score += self.window_counts[word];
+        self.window_counts[word] += 1;
+        self.suffix_score += self.suffix_counts[word];
+        self.suffix_counts[word] += 1;
+
+        if self.suffix_counts[word] * 10 > self.threshold << 1 {
+            loop {
+                let dropped = self.words[self.words.len() - self.suffix_len];
+                self.suffix_counts[dropped] -= 1;
+                self.suffix_score -= self.suffix_counts[dropped];
+                self.suffix_len -= 1;
+                if dropped == word {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Saves the best perfect interval once it has fallen out of the window
+    fn save_masked_regions(&mut self, start: usize) {
+        let best = match self.perfect.last() {
+            Some(p) if p.start < start => p,
+            _ => return,
+        };
+
+        match self.masked.last_mut() {
+            // Merge with the previous interval if they overlap
+            Some(last) if best.start <= last.1 => last.1 = last.1.max(best.finish),
+            _ => self.masked.push((best.start, best.finish)),
+        }
+
+        // Remove perfect intervals that have fallen out of the window
+        let kept = self
+            .perfect
+            .iter()
+            .rposition(|p| p.start >= start)
+            .map_or(0, |i| i + 1);
+        self.perfect.truncate(kept);
+    }
+
+    /// Records the perfect intervals ending at the last word of the window
+    fn find_perfect(&mut self, start: usize) {
+        let mut counts = self.suffix_counts;
+        let mut score = self.suffix_score;
+        let mut max_score = 0;
+        let mut max_len = 0;
+
+        for i in (0..self.words.len() - self.suffix_len).rev() {
+            let word = self.words[i];
+            score += counts[word];
+            counts[word] += 1;
+            let new_len = (self.words.len() - i - 1) as i64;
+
+            if score * 10 > self.threshold * new_len {
+                // Find the insertion position
+                let mut j = 0;
+                while j < self.perfect.len() && self.perfect[j].start >= i + start {
+                    let p = &self.perfect[j];
+                    if max_score == 0 || p.score * max_len > max_score * p.length {
+                        max_score = p.score;
+                        max_len = p.length;
+                    }
+                    j += 1;
+                }
+
+                if max_score == 0 || score * max_len >= max_score * new_len {
+                    max_score = score;
+                    max_len = new_len;
+                    self.perfect.insert(
+                        j,
+                        PerfectInterval {
+                            start: i + start,
+                            finish: self.words.len() + (WORD_LEN - 1) + start,
+                            score,
+                            length: new_len,
+                        },
+                    );
+                }
+            }
+        }
+    }
+}
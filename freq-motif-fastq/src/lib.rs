@@ -0,0 +1,5112 @@
+//! Statistics on the dinucleotides, trinucleotides and low-complexity bases of FASTQ reads.
+//!
+//! [`analyze_reads`] is the simplest entry point: it counts the motifs of all the reads of a
+//! FASTQ reader (see [`open_fastq`]) with the given [`MotifOptions`] and returns their
+//! [`MotifStats`]. [`process_reads_and_write_fasta`] and [`compute_motif_stats`] give control over
+//! the selection of the reads and over the low-complexity detection.
+
+use bzip2::read::MultiBzDecoder;
+use checkpoint::CheckpointOptions;
+use clap::ValueEnum;
+use dust::{DEFAULT_THRESHOLD, DEFAULT_WINDOW};
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression as GzCompression;
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{debug, info, warn};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use regex::bytes::Regex;
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::mem::size_of;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{self, AtomicU64};
+use std::sync::Arc;
+use std::time::Instant;
+use tempfile::NamedTempFile;
+
+pub mod checkpoint;
+pub mod dust;
+pub mod plot;
+
+/// Format of the input records
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum InputFormat {
+    /// FASTA if the first record starts with '>', FASTQ otherwise
+    Auto,
+    Fastq,
+    Fasta,
+}
+
+/// Bases of the reads, DNA (A/C/G/T) or RNA (A/C/G/U)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum Alphabet {
+    Dna,
+    Rna,
+}
+
+impl Alphabet {
+    /// The four uppercase bases, in the order of the per-base statistics
+    pub fn bases(self) -> [u8; 4] {
+        match self {
+            Alphabet::Dna => *b"ACGT",
+            Alphabet::Rna => *b"ACGU",
+        }
+    }
+
+    /// Index of a base in [`Alphabet::bases`], whatever its case
+    fn index(self, base: u8) -> Option<usize> {
+        let base = base.to_ascii_uppercase();
+        self.bases().iter().position(|&b| b == base)
+    }
+}
+
+/// Handling of motifs containing a base other than A/C/G/T
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AmbiguousMotifs {
+    Skip,
+    Bucket,
+}
+
+/// Value reported for each motif
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Metric {
+    /// Percentage of the reads in which the motif exceeds the minimum proportion
+    Presence,
+    /// Mean over the reads of the percentage of their motifs of the same length that it makes up
+    MeanFrequency,
+}
+
+/// Weight of each read in the proportions of the motifs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Weight {
+    /// Every read counts the same
+    ByRead,
+    /// Every read counts as many times as it has bases in its analyzed part, where its motifs
+    /// and masked bases are found, so that short reads weigh less
+    ByBase,
+}
+
+/// Unit of the proportions saved to the results files, computed as percentages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Units {
+    Percent,
+    Fraction,
+}
+
+impl Units {
+    /// Converts a percentage to this unit
+    pub fn convert(self, percent: f64) -> f64 {
+        match self {
+            Units::Percent => percent,
+            Units::Fraction => percent / 100.0,
+        }
+    }
+
+    /// Formats a percentage in this unit, fractions keeping the same precision
+    pub fn format(self, percent: f64) -> String {
+        match self {
+            Units::Percent => format!("{:.4}", percent),
+            Units::Fraction => format!("{:.6}", percent / 100.0),
+        }
+    }
+
+    /// Name of the column of the proportions in this unit
+    pub fn column(self) -> &'static str {
+        match self {
+            Units::Percent => "Proportion",
+            Units::Fraction => "Fraction",
+        }
+    }
+}
+
+/// Statistic of the per-read frequencies reported for each motif
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AggregateStat {
+    Mean,
+    Median,
+    /// 90th percentile
+    P90,
+}
+
+impl AggregateStat {
+    /// Whether the frequency of each motif in each read has to be kept to compute it
+    fn needs_read_frequencies(self) -> bool {
+        self != AggregateStat::Mean
+    }
+
+    /// Statistic of `count` values, the ones missing from `values` being 0.
+    /// Percentiles are interpolated between the closest ranks.
+    fn compute(self, values: &[f64], count: usize) -> f64 {
+        let quantile = match self {
+            AggregateStat::Mean => return values.iter().sum::<f64>() / count as f64,
+            AggregateStat::Median => 0.5,
+            AggregateStat::P90 => 0.9,
+        };
+        let mut sorted = vec![0.0; count.saturating_sub(values.len())];
+        sorted.extend_from_slice(values);
+        sorted.sort_by(f64::total_cmp);
+        let rank = quantile * (sorted.len() - 1) as f64;
+        let lower = sorted[rank.floor() as usize];
+        let upper = sorted[rank.ceil() as usize];
+        lower + (upper - lower) * rank.fract()
+    }
+}
+
+/// Handling of the reads longer than the maximum read length
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LongReads {
+    Truncate,
+    Skip,
+}
+
+/// Errors aborting the analysis, each class having its own exit code.
+/// Code 2 is left to clap for invalid command lines.
+#[derive(Debug)]
+pub enum AppError {
+    /// The input file could not be opened or read
+    Input(String),
+    /// The output directory or one of the output files could not be written
+    Output(String),
+    /// SDUST could not be run or its output could not be parsed
+    Sdust(String),
+    /// The barplot could not be generated
+    Plot(String),
+    /// The input holds no read at all, as left by a failed upstream step
+    EmptyInput(String),
+}
+
+impl AppError {
+    /// Exit code reported to the calling process
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            AppError::Input(_) => 3,
+            AppError::Output(_) => 4,
+            AppError::Sdust(_) => 5,
+            AppError::Plot(_) => 6,
+            AppError::EmptyInput(_) => 7,
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::Input(msg)
+            | AppError::Output(msg)
+            | AppError::Sdust(msg)
+            | AppError::Plot(msg)
+            | AppError::EmptyInput(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl Error for AppError {}
+
+/// Settings controlling how motifs are counted in each read
+pub struct MotifOptions {
+    /// Lengths of the motifs to count
+    pub kmer_lengths: Vec<usize>,
+    /// Motifs to count instead of all the motifs of `kmer_lengths`, if any
+    pub motifs: Option<Vec<String>>,
+    /// Minimum proportion of a read a motif must reach to be counted (as a fraction)
+    pub min_proportion: f64,
+    /// Minimum proportion of the motifs of some lengths, overriding `min_proportion` for them
+    pub kmer_min_proportions: HashMap<usize, f64>,
+    /// Count motifs together with their reverse complement
+    pub canonical: bool,
+    /// Also count the motifs of the reverse complement of each read
+    pub both_strands: bool,
+    /// Count lowercase and uppercase motifs separately
+    pub preserve_case: bool,
+    /// Handling of motifs containing ambiguous bases
+    pub ambiguous: AmbiguousMotifs,
+    /// Read-length bins to break the counts down by, if any
+    pub length_bins: Option<LengthBins>,
+    /// Compute the GC content of the reads
+    pub gc_content: bool,
+    /// Count motifs on consecutive, non-overlapping windows instead of at every position
+    pub non_overlapping: bool,
+    /// Minimum mean Phred quality of the reads to analyze, if any. Reads without qualities
+    /// (FASTA) are kept
+    pub min_mean_quality: Option<f64>,
+    /// Offset of the ASCII encoding of the quality scores (33 or 64)
+    pub phred_offset: u8,
+    /// Format of the input records
+    pub input_format: InputFormat,
+    /// Number of bases trimmed from the start of each read before analysis
+    pub trim_start: usize,
+    /// Number of bases trimmed from the end of each read before analysis
+    pub trim_end: usize,
+    /// Motifs whose occurrences are profiled along the reads, none by default
+    pub position_motifs: Vec<String>,
+    /// Window size of the built-in DUST
+    pub dust_window: usize,
+    /// Score threshold of the built-in DUST
+    pub dust_threshold: i64,
+    /// Compute the statistics of the homopolymer runs of the reads
+    pub homopolymers: bool,
+    /// Count the dinucleotides of the reads, whatever their proportion in each read
+    pub dinucleotide_composition: bool,
+    /// Compute the enrichment of each motif over its frequency expected from the base
+    /// composition of the reads
+    pub enrichment: bool,
+    /// Minimum Shannon entropy of the bases of a read, in bits, below which it is counted in the
+    /// LowEntropy category, if any
+    pub min_entropy: Option<f64>,
+    /// Minimum length of the reads, once trimmed, the shorter ones being skipped
+    pub min_read_length: usize,
+    /// Maximum length of the reads, once trimmed, if any
+    pub max_read_length: Option<usize>,
+    /// Handling of the reads longer than `max_read_length`
+    pub long_reads: LongReads,
+    /// Value reported for each motif
+    pub metric: Metric,
+    /// Weight of each read in the proportions. Weighted by base, the counts are numbers of
+    /// analyzed bases, and only the mean of the per-read frequencies is computed
+    pub weight: Weight,
+    /// Statistic of the per-read frequencies reported for each motif instead of `metric`, if any.
+    /// The median and percentiles keep the frequencies of every read in memory.
+    pub aggregate_stat: Option<AggregateStat>,
+    /// Bases of the reads, which the generated motifs are made of
+    pub alphabet: Alphabet,
+    /// Read the consecutive records of the input as the two mates of paired-end reads
+    pub interleaved: bool,
+    /// Fail on a FASTQ record whose quality length differs from its sequence length instead of
+    /// skipping it
+    pub validate_quality_length: bool,
+    /// Pattern the name of a read (its header without the leading `@` or `>`) must match for
+    /// the read to be analyzed, if any
+    pub name_filter: Option<Regex>,
+    /// Break the counts down by the Illumina tile the reads come from, read from their names
+    pub by_tile: bool,
+    /// Keep the name of each read for the per-read outputs
+    pub read_names: bool,
+    /// Keep the intervals masked by the built-in DUST in each read, for the masked reads to be
+    /// saved. The reads written to a FASTA writer are then masked with the built-in DUST too
+    pub dust_intervals: bool,
+    /// Count the motifs inside and outside the regions masked by the built-in DUST separately
+    pub masked_composition: bool,
+    /// Memory the counts may take, in bytes, above which a warning is given, if any
+    pub max_memory: Option<usize>,
+    /// Checkpoints to save periodically and to resume from, if any. Only the first reads of an
+    /// input masked with the built-in DUST are processed from a checkpoint
+    pub checkpoint: Option<CheckpointOptions>,
+}
+
+impl Default for MotifOptions {
+    /// Dinucleotides and trinucleotides above 15% of a read, with the GC content
+    fn default() -> MotifOptions {
+        MotifOptions {
+            kmer_lengths: vec![2, 3],
+            motifs: None,
+            min_proportion: 0.15,
+            kmer_min_proportions: HashMap::new(),
+            canonical: false,
+            both_strands: false,
+            preserve_case: false,
+            ambiguous: AmbiguousMotifs::Skip,
+            length_bins: None,
+            gc_content: true,
+            non_overlapping: false,
+            min_mean_quality: None,
+            phred_offset: 33,
+            input_format: InputFormat::Auto,
+            trim_start: 0,
+            trim_end: 0,
+            position_motifs: Vec::new(),
+            dust_window: DEFAULT_WINDOW,
+            dust_threshold: DEFAULT_THRESHOLD,
+            homopolymers: false,
+            dinucleotide_composition: false,
+            enrichment: false,
+            min_entropy: None,
+            min_read_length: MIN_READ_LENGTH,
+            max_read_length: None,
+            long_reads: LongReads::Truncate,
+            metric: Metric::Presence,
+            weight: Weight::ByRead,
+            aggregate_stat: None,
+            name_filter: None,
+            by_tile: false,
+            read_names: false,
+            dust_intervals: false,
+            masked_composition: false,
+            max_memory: None,
+            checkpoint: None,
+            alphabet: Alphabet::Dna,
+            interleaved: false,
+            validate_quality_length: false,
+        }
+    }
+}
+
+impl MotifOptions {
+    /// Minimum proportion a motif of length `k` must reach in a read to be counted
+    fn kmer_min_proportion(&self, k: usize) -> f64 {
+        self.kmer_min_proportions
+            .get(&k)
+            .copied()
+            .unwrap_or(self.min_proportion)
+    }
+
+    /// Statistic of the per-read frequencies reported for each motif, if any
+    fn frequency_stat(&self) -> Option<AggregateStat> {
+        self.aggregate_stat
+            .or((self.metric == Metric::MeanFrequency).then_some(AggregateStat::Mean))
+    }
+}
+
+/// Label of the category gathering motifs with ambiguous bases
+pub const AMBIGUOUS_MOTIF: &str = "Ambiguous";
+/// Label of the category gathering low-complexity reads
+pub const LOW_COMPLEXITY_MOTIF: &str = "LowComplexity";
+/// Label of the category gathering reads whose base entropy is below the minimum
+pub const LOW_ENTROPY_MOTIF: &str = "LowEntropy";
+
+/// Read-length bins, delimited by their upper bounds
+#[derive(Debug)]
+pub struct LengthBins {
+    bounds: Vec<usize>,
+}
+
+impl LengthBins {
+    pub fn new(mut bounds: Vec<usize>) -> LengthBins {
+        bounds.sort_unstable();
+        bounds.dedup();
+        LengthBins { bounds }
+    }
+
+    /// Labels of all the bins, by increasing read length
+    fn labels(&self) -> Vec<String> {
+        let mut labels: Vec<String> = self
+            .bounds
+            .iter()
+            .enumerate()
+            .map(|(i, &upper)| self.range_label(i, upper))
+            .collect();
+        labels.push(self.last_label());
+        labels
+    }
+
+    /// Label of the bin a read length falls into, bins including their lower bound
+    fn label(&self, length: usize) -> String {
+        match self.bounds.iter().position(|&upper| length < upper) {
+            Some(i) => self.range_label(i, self.bounds[i]),
+            None => self.last_label(),
+        }
+    }
+
+    fn range_label(&self, index: usize, upper: usize) -> String {
+        let lower = if index == 0 {
+            0
+        } else {
+            self.bounds[index - 1]
+        };
+        format!("{}-{}", lower, upper)
+    }
+
+    fn last_label(&self) -> String {
+        format!("{}+", self.bounds.last().copied().unwrap_or(0))
+    }
+}
+
+/// Tells whether a base is one of A/C/G/T, in either case
+fn is_nucleotide(base: u8, alphabet: Alphabet) -> bool {
+    alphabet.index(base).is_some()
+}
+
+/// Returns the reverse complement of a motif, keeping its case and leaving bases out of the
+/// alphabet untouched
+fn reverse_complement(motif: &[u8], alphabet: Alphabet) -> Vec<u8> {
+    let [a, c, g, t] = alphabet.bases();
+    motif
+        .iter()
+        .rev()
+        .map(|&base| {
+            let complement = match base.to_ascii_uppercase() {
+                upper if upper == a => t,
+                upper if upper == t => a,
+                upper if upper == g => c,
+                upper if upper == c => g,
+                _ => return base,
+            };
+            if base.is_ascii_lowercase() {
+                complement.to_ascii_lowercase()
+            } else {
+                complement
+            }
+        })
+        .collect()
+}
+
+/// Returns the lexicographically smaller of a motif and its reverse complement
+fn canonical_motif(motif: &[u8], alphabet: Alphabet) -> String {
+    let reverse = reverse_complement(motif, alphabet);
+    let canonical = if reverse.as_slice() < motif {
+        &reverse
+    } else {
+        motif
+    };
+    String::from_utf8_lossy(canonical).into_owned()
+}
+
+/// Initializes all possible motifs of the requested lengths with 0 proportions.
+/// Only canonical representatives are emitted when motifs are counted with their reverse complement.
+fn initialize_all_motifs(options: &MotifOptions) -> HashMap<String, f64> {
+    let mut all_motifs = HashMap::new();
+    let bases: Vec<String> = options
+        .alphabet
+        .bases()
+        .iter()
+        .map(|&base| (base as char).to_string())
+        .collect();
+
+    if let Some(panel) = motif_panel(options) {
+        all_motifs.extend(panel.into_iter().map(|motif| (motif, 0.0)));
+    } else {
+        for &k in &options.kmer_lengths {
+            let mut motifs = vec![String::new()];
+            for _ in 0..k {
+                motifs = motifs
+                    .iter()
+                    .flat_map(|prefix| bases.iter().map(move |b| format!("{}{}", prefix, b)))
+                    .collect();
+            }
+            for motif in motifs {
+                if !options.canonical
+                    || canonical_motif(motif.as_bytes(), options.alphabet) == motif
+                {
+                    all_motifs.insert(motif, 0.0);
+                }
+            }
+        }
+    }
+
+    if options.ambiguous == AmbiguousMotifs::Bucket {
+        all_motifs.insert(AMBIGUOUS_MOTIF.to_string(), 0.0);
+    }
+    if options.min_entropy.is_some() {
+        all_motifs.insert(LOW_ENTROPY_MOTIF.to_string(), 0.0);
+    }
+
+    all_motifs
+}
+
+/// Motifs of the panel, as they are counted (canonical with `canonical`), when one is set
+fn motif_panel(options: &MotifOptions) -> Option<HashSet<String>> {
+    let panel = options.motifs.as_ref()?;
+    Some(
+        panel
+            .iter()
+            .map(|motif| {
+                if options.canonical {
+                    canonical_motif(motif.as_bytes(), options.alphabet)
+                } else {
+                    motif.clone()
+                }
+            })
+            .collect(),
+    )
+}
+
+/// Lengths of the counted motifs: the ones of the motif panel when set
+fn motif_lengths(options: &MotifOptions) -> Vec<usize> {
+    match &options.motifs {
+        Some(panel) => {
+            let mut lengths: Vec<usize> = panel.iter().map(String::len).collect();
+            lengths.sort_unstable();
+            lengths.dedup();
+            lengths
+        }
+        None => options.kmer_lengths.clone(),
+    }
+}
+
+/// Compression formats recognised on input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    Gzip,
+    Bzip2,
+    Zstd,
+    Plain,
+}
+
+impl Compression {
+    /// Guesses the compression format from the file extension
+    fn from_extension(filename: &str) -> Compression {
+        if filename.ends_with(".gz") {
+            Compression::Gzip
+        } else if filename.ends_with(".bz2") {
+            Compression::Bzip2
+        } else if filename.ends_with(".zst") {
+            Compression::Zstd
+        } else {
+            Compression::Plain
+        }
+    }
+
+    /// Detects the compression format from the leading magic bytes
+    fn from_magic(magic: &[u8]) -> Compression {
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if magic.starts_with(b"BZh") {
+            Compression::Bzip2
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        } else {
+            Compression::Plain
+        }
+    }
+}
+
+/// Opens a FASTQ file, handling plain, gzip, bzip2 and zstd formats.
+/// A filename of `-` reads from standard input.
+pub fn open_fastq(filename: &str) -> io::Result<Box<dyn BufRead>> {
+    open_fastq_with_position(filename).map(|(reader, _)| reader)
+}
+
+/// Opens a FASTQ file like [`open_fastq`], with the position reached in it, unless it is
+/// stdin, to estimate how much of it is left
+pub fn open_fastq_with_position(
+    filename: &str,
+) -> io::Result<(Box<dyn BufRead>, Option<InputPosition>)> {
+    if filename == "-" {
+        let reader = decompress(BufReader::new(io::stdin()), filename, Compression::Plain)?;
+        return Ok((reader, None));
+    }
+
+    let file = File::open(filename)?;
+    let position = InputPosition {
+        bytes_read: Arc::new(AtomicU64::new(0)),
+        size: file.metadata()?.len(),
+        start: Arc::new(AtomicU64::new(NOT_ESTIMATED)),
+        limit: Arc::new(AtomicU64::new(NOT_ESTIMATED)),
+    };
+    let reader = BufReader::new(CountingReader {
+        inner: file,
+        bytes_read: Arc::clone(&position.bytes_read),
+    });
+    let reader = decompress(reader, filename, Compression::from_extension(filename))?;
+    Ok((reader, Some(position)))
+}
+
+/// Bytes read so far from an input file of known size, before decompression, from which the
+/// number of reads to go through is estimated, since it cannot be told from the size of a
+/// compressed input
+#[derive(Clone)]
+pub struct InputPosition {
+    bytes_read: Arc<AtomicU64>,
+    size: u64,
+    /// Bytes read when the reads started being counted, after the skipped ones, or
+    /// [`NOT_ESTIMATED`] until then
+    start: Arc<AtomicU64>,
+    /// Maximum number of reads to go through, [`NOT_ESTIMATED`] for the whole input
+    limit: Arc<AtomicU64>,
+}
+
+/// Start or limit of an [`InputPosition`] not set
+const NOT_ESTIMATED: u64 = u64::MAX;
+
+impl InputPosition {
+    /// Marks where the counted reads start, with the maximum number of them if any
+    fn mark_start(&self, limit: Option<usize>) {
+        let limit = limit.map_or(NOT_ESTIMATED, |limit| limit as u64);
+        self.limit.store(limit, atomic::Ordering::Relaxed);
+        self.start.store(
+            self.bytes_read.load(atomic::Ordering::Relaxed),
+            atomic::Ordering::Relaxed,
+        );
+    }
+
+    /// Approximate number of reads to go through, given the ones counted since the start:
+    /// the limit unless the rest of the input, holding as many reads per byte, ends before it
+    fn estimated_total(&self, count: usize) -> Option<usize> {
+        let start = self.start.load(atomic::Ordering::Relaxed);
+        let bytes_read = self.bytes_read.load(atomic::Ordering::Relaxed);
+        let counted_bytes = bytes_read.saturating_sub(start);
+        if start == NOT_ESTIMATED || counted_bytes == 0 {
+            return None;
+        }
+        let left = self.size.saturating_sub(bytes_read) as f64;
+        let total = count as u64 + (left * count as f64 / counted_bytes as f64) as u64;
+        let limit = self.limit.load(atomic::Ordering::Relaxed);
+        Some(total.min(limit).max(count as u64) as usize)
+    }
+}
+
+/// Reader adding up the bytes read from it, for an [`InputPosition`]
+struct CountingReader<R> {
+    inner: R,
+    bytes_read: Arc<AtomicU64>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.bytes_read
+            .fetch_add(read as u64, atomic::Ordering::Relaxed);
+        Ok(read)
+    }
+}
+
+/// Wraps a reader in the decoder matching its content.
+/// The magic bytes take precedence over the extension so that a mislabeled file still works.
+/// Gzip and bzip2 inputs may be made of several concatenated members, as written by `bgzip`,
+/// `pbzip2` or `cat`, which are all read.
+fn decompress<R: BufRead + 'static>(
+    mut reader: R,
+    filename: &str,
+    from_extension: Compression,
+) -> io::Result<Box<dyn BufRead>> {
+    let buffer = reader.fill_buf()?;
+    let from_magic = Compression::from_magic(buffer);
+
+    // An empty file, as left by a failed upstream step, is read as an empty plain input
+    if from_magic == Compression::Plain
+        && from_extension != Compression::Plain
+        && !buffer.is_empty()
+    {
+        warn!(
+            "{} has a {:?} extension but does not look {:?}-compressed, reading it as plain text",
+            filename, from_extension, from_extension
+        );
+    }
+
+    match from_magic {
+        Compression::Gzip => Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader)))),
+        Compression::Bzip2 => Ok(Box::new(BufReader::new(MultiBzDecoder::new(reader)))),
+        Compression::Zstd => {
+            let decoder = zstd::stream::read::Decoder::with_buffer(reader).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("Failed to initialise zstd decoder: {}", e),
+                )
+            })?;
+            Ok(Box::new(BufReader::new(decoder)))
+        }
+        Compression::Plain => Ok(Box::new(reader)),
+    }
+}
+
+/// An output file, gzip-compressed when its name ends with `.gz`
+pub enum OutputFile {
+    Plain(io::BufWriter<File>),
+    Gzip(GzEncoder<io::BufWriter<File>>),
+}
+
+impl OutputFile {
+    pub fn create(path: &Path) -> io::Result<OutputFile> {
+        let writer = io::BufWriter::new(File::create(path)?);
+        if path.extension().is_some_and(|extension| extension == "gz") {
+            Ok(OutputFile::Gzip(GzEncoder::new(
+                writer,
+                GzCompression::default(),
+            )))
+        } else {
+            Ok(OutputFile::Plain(writer))
+        }
+    }
+
+    /// Writes the end of the file, which is only done on drop otherwise, ignoring errors
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            OutputFile::Plain(mut writer) => writer.flush(),
+            OutputFile::Gzip(encoder) => encoder.finish()?.flush(),
+        }
+    }
+}
+
+impl Write for OutputFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            OutputFile::Plain(writer) => writer.write(buf),
+            OutputFile::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            OutputFile::Plain(writer) => writer.flush(),
+            OutputFile::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Output file written to a temporary file of its directory and renamed into place by
+/// [`AtomicFile::commit`], so that it never appears half-written. The temporary file is removed
+/// if dropped before then
+pub struct AtomicFile {
+    writer: io::BufWriter<NamedTempFile>,
+    path: PathBuf,
+}
+
+impl AtomicFile {
+    pub fn create(path: &Path) -> io::Result<AtomicFile> {
+        let directory = match path.parent() {
+            Some(directory) if !directory.as_os_str().is_empty() => directory,
+            _ => Path::new("."),
+        };
+        let mut builder = tempfile::Builder::new();
+        // Temporary files are private by default, unlike the files they stand for
+        #[cfg(unix)]
+        builder.permissions(std::os::unix::fs::PermissionsExt::from_mode(0o666));
+        let file = builder.tempfile_in(directory)?;
+        Ok(AtomicFile {
+            writer: io::BufWriter::new(file),
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Moves the complete file into place
+    pub fn commit(self) -> io::Result<()> {
+        let file = self.writer.into_inner().map_err(|e| e.into_error())?;
+        file.persist(&self.path).map_err(|e| e.error)?;
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Longest motifs counted from `kmer_lengths`, all of which are listed up front: 4^12, about 17
+/// million, motifs of 12 bases
+pub const MAX_KMER_LENGTH: usize = 12;
+
+/// Default minimum length of the analyzed reads, once trimmed
+pub const MIN_READ_LENGTH: usize = 50;
+/// Fraction of the reads above which too many short reads are warned about
+const MAX_SHORT_READ_FRACTION: f64 = 0.1;
+
+/// Number of reads whose motifs are counted together in parallel
+const CHUNK_SIZE: usize = 10_000;
+
+/// Counts accumulated over the analyzed reads
+#[derive(Serialize, Deserialize)]
+pub struct ReadStats {
+    /// Number of reads in which each motif exceeds the minimum proportion
+    #[serde(with = "checkpoint::pairs")]
+    pub motif_counts: HashMap<Motif, u64>,
+    /// Number of analyzed reads
+    pub total_reads: usize,
+    /// Full length of each read, in the order the reads were selected
+    pub read_lengths: Vec<usize>,
+    /// Number of masked bases in each read, in the same order
+    pub masked_bases: Vec<usize>,
+    /// Length of the analyzed part of each read, in the same order, when weighted by base
+    pub analyzed_lengths: Vec<usize>,
+    /// Motif counts by read-length bin and motif, when bins are set
+    #[serde(with = "checkpoint::pairs")]
+    pub binned_counts: HashMap<(String, Motif), u64>,
+    /// Motif counts by tile and motif, when broken down by tile
+    #[serde(with = "checkpoint::pairs")]
+    pub tile_counts: HashMap<(String, Motif), u64>,
+    /// Tile of each read, in the same order, when broken down by tile
+    pub read_tiles: Vec<String>,
+    /// Name of each read, in the same order, when the names are kept
+    pub read_names: Vec<String>,
+    /// Intervals masked by the built-in DUST in each read, in the same order, when kept
+    pub dust_intervals: Vec<Vec<(usize, usize)>>,
+    /// GC content of the reads, when computed
+    pub gc_stats: Option<GcStats>,
+    /// Motif counts of each mate, for paired-end reads
+    pub mate_counts: Option<MateCounts>,
+    /// Sum of the mean qualities of the reads that have qualities
+    pub quality_sum: f64,
+    /// Number of reads that have qualities
+    pub quality_reads: usize,
+    /// Number of reads left out for being shorter than `min_read_length`, once trimmed
+    pub short_reads: usize,
+    /// Occurrences of each profiled motif at each position of the analyzed part of the reads
+    pub position_counts: Vec<Vec<u64>>,
+    /// Number of reads whose analyzed part covers each position
+    pub position_reads: Vec<u64>,
+    /// Homopolymer runs of the reads, when computed
+    pub homopolymer_stats: Option<HomopolymerStats>,
+    /// Base and motif occurrences of the reads, when the enrichment is computed
+    pub enrichment_stats: Option<EnrichmentStats>,
+    /// Distribution of the base entropy of the reads, when a minimum entropy is set
+    pub entropy_stats: Option<EntropyStats>,
+    /// Dinucleotides of all the reads, when their composition is computed
+    pub dinucleotide_stats: Option<DinucleotideStats>,
+    /// Bases of all the reads
+    pub base_composition: BaseComposition,
+    /// Motifs inside and outside the masked regions, when counted separately
+    pub masked_composition: Option<MaskedComposition>,
+    /// Proportion of the most frequent motif of each read
+    pub max_motif_stats: MaxMotifStats,
+    /// Sum over the reads of the frequency of each motif, with the mean-frequency metric
+    #[serde(with = "checkpoint::pairs")]
+    pub frequency_sums: HashMap<Motif, f64>,
+    /// Frequency of each motif in each read it is found in, with the median or a percentile
+    #[serde(with = "checkpoint::pairs")]
+    pub read_frequencies: HashMap<Motif, Vec<f64>>,
+}
+
+/// Rough number of bytes taken by a hash map, from the room reserved for its entries and their
+/// control bytes, leaving out what they point to
+fn map_memory<K, V>(map: &HashMap<K, V>) -> usize {
+    map.capacity() * (size_of::<(K, V)>() + 1)
+}
+
+impl ReadStats {
+    /// Rough number of bytes taken by the counts that grow with the input: the maps of motifs
+    /// and the lists of the reads, leaving out the fixed-size histograms and the text of the
+    /// names of the motifs and the tiles
+    pub fn estimated_memory(&self) -> usize {
+        let mut memory = map_memory(&self.motif_counts)
+            + map_memory(&self.binned_counts)
+            + map_memory(&self.tile_counts)
+            + map_memory(&self.frequency_sums)
+            + map_memory(&self.read_frequencies)
+            + (self.read_lengths.capacity()
+                + self.masked_bases.capacity()
+                + self.analyzed_lengths.capacity())
+                * size_of::<usize>()
+            + (self.read_tiles.capacity() + self.read_names.capacity()) * size_of::<String>();
+        memory += self
+            .read_frequencies
+            .values()
+            .map(|frequencies| frequencies.capacity() * size_of::<f64>())
+            .sum::<usize>();
+        memory += self.dust_intervals.capacity() * size_of::<Vec<(usize, usize)>>()
+            + self
+                .dust_intervals
+                .iter()
+                .map(|intervals| intervals.capacity() * size_of::<(usize, usize)>())
+                .sum::<usize>();
+        if let Some(mate_counts) = &self.mate_counts {
+            memory += mate_counts
+                .motif_counts
+                .iter()
+                .map(map_memory)
+                .sum::<usize>()
+                + mate_counts.mates.capacity();
+        }
+        if let Some(homopolymer_stats) = &self.homopolymer_stats {
+            memory += homopolymer_stats.longest_runs.capacity() * size_of::<Option<(u8, usize)>>();
+        }
+        if let Some(enrichment_stats) = &self.enrichment_stats {
+            memory += map_memory(&enrichment_stats.motif_occurrences);
+        }
+        if let Some(masked_composition) = &self.masked_composition {
+            memory += masked_composition
+                .counts
+                .iter()
+                .map(map_memory)
+                .sum::<usize>();
+        }
+        memory
+    }
+
+    /// Adds the counts of other reads, whose indices follow the ones of these reads
+    pub fn merge(&mut self, other: ReadStats) {
+        for (motif, count) in other.motif_counts {
+            *self.motif_counts.entry(motif).or_insert(0) += count;
+        }
+        for (motif, frequency) in other.frequency_sums {
+            *self.frequency_sums.entry(motif).or_insert(0.0) += frequency;
+        }
+        for (motif, frequencies) in other.read_frequencies {
+            self.read_frequencies
+                .entry(motif)
+                .or_default()
+                .extend(frequencies);
+        }
+        self.total_reads += other.total_reads;
+        self.read_lengths.extend(other.read_lengths);
+        self.masked_bases.extend(other.masked_bases);
+        self.quality_sum += other.quality_sum;
+        self.quality_reads += other.quality_reads;
+        self.short_reads += other.short_reads;
+        for (counts, other_counts) in self.position_counts.iter_mut().zip(other.position_counts) {
+            add_position_counts(counts, &other_counts);
+        }
+        add_position_counts(&mut self.position_reads, &other.position_reads);
+        for (key, count) in other.binned_counts {
+            *self.binned_counts.entry(key).or_insert(0) += count;
+        }
+        for (key, count) in other.tile_counts {
+            *self.tile_counts.entry(key).or_insert(0) += count;
+        }
+        self.read_tiles.extend(other.read_tiles);
+        self.read_names.extend(other.read_names);
+        self.analyzed_lengths.extend(other.analyzed_lengths);
+        self.dust_intervals.extend(other.dust_intervals);
+        if let (Some(gc_stats), Some(other)) = (self.gc_stats.as_mut(), other.gc_stats) {
+            gc_stats.merge(other);
+        }
+        if let (Some(homopolymer_stats), Some(other)) =
+            (self.homopolymer_stats.as_mut(), other.homopolymer_stats)
+        {
+            homopolymer_stats.merge(other);
+        }
+        if let (Some(enrichment_stats), Some(other)) =
+            (self.enrichment_stats.as_mut(), other.enrichment_stats)
+        {
+            enrichment_stats.merge(other);
+        }
+        if let (Some(entropy_stats), Some(other)) =
+            (self.entropy_stats.as_mut(), other.entropy_stats)
+        {
+            entropy_stats.merge(other);
+        }
+        if let (Some(dinucleotide_stats), Some(other)) =
+            (self.dinucleotide_stats.as_mut(), other.dinucleotide_stats)
+        {
+            dinucleotide_stats.merge(other);
+        }
+        self.base_composition.merge(other.base_composition);
+        self.max_motif_stats.merge(other.max_motif_stats);
+        if let (Some(masked_composition), Some(other)) =
+            (self.masked_composition.as_mut(), other.masked_composition)
+        {
+            masked_composition.merge(other);
+        }
+        // Mates are only told apart when all the reads are paired-end
+        self.mate_counts = match (self.mate_counts.take(), other.mate_counts) {
+            (Some(mut mate_counts), Some(other)) => {
+                mate_counts.merge(other);
+                Some(mate_counts)
+            }
+            _ => None,
+        };
+    }
+
+    /// Counts of no read, for paired-end reads or not
+    pub fn new(options: &MotifOptions, paired: bool) -> ReadStats {
+        ReadStats {
+            motif_counts: HashMap::new(),
+            total_reads: 0,
+            read_lengths: Vec::new(),
+            masked_bases: Vec::new(),
+            analyzed_lengths: Vec::new(),
+            binned_counts: HashMap::new(),
+            tile_counts: HashMap::new(),
+            read_tiles: Vec::new(),
+            read_names: Vec::new(),
+            dust_intervals: Vec::new(),
+            gc_stats: options.gc_content.then(GcStats::new),
+            mate_counts: paired.then(MateCounts::new),
+            quality_sum: 0.0,
+            quality_reads: 0,
+            short_reads: 0,
+            position_counts: vec![Vec::new(); options.position_motifs.len()],
+            position_reads: Vec::new(),
+            homopolymer_stats: options
+                .homopolymers
+                .then(|| HomopolymerStats::new(options.alphabet)),
+            enrichment_stats: options
+                .enrichment
+                .then(|| EnrichmentStats::new(options.alphabet)),
+            entropy_stats: options.min_entropy.map(EntropyStats::new),
+            dinucleotide_stats: options
+                .dinucleotide_composition
+                .then(|| DinucleotideStats::new(options.alphabet)),
+            base_composition: BaseComposition::new(options.alphabet),
+            masked_composition: options.masked_composition.then(MaskedComposition::default),
+            max_motif_stats: MaxMotifStats::new(),
+            frequency_sums: HashMap::new(),
+            read_frequencies: HashMap::new(),
+        }
+    }
+}
+
+/// Adds per-position counts to running totals, growing them as needed
+fn add_position_counts(totals: &mut Vec<u64>, counts: &[u64]) {
+    if totals.len() < counts.len() {
+        totals.resize(counts.len(), 0);
+    }
+    for (total, count) in totals.iter_mut().zip(counts) {
+        *total += count;
+    }
+}
+
+/// GC content of the analyzed reads, computed over their A/C/G/T bases
+#[derive(Serialize, Deserialize)]
+pub struct GcStats {
+    gc_bases: u64,
+    acgt_bases: u64,
+    /// Number of reads by GC percentage, rounded to the nearest integer
+    #[serde(with = "checkpoint::long_array")]
+    histogram: [u64; 101],
+}
+
+impl GcStats {
+    fn new() -> GcStats {
+        GcStats {
+            gc_bases: 0,
+            acgt_bases: 0,
+            histogram: [0; 101],
+        }
+    }
+
+    /// Adds the G/C and A/C/G/T base counts of a read
+    fn add_read(&mut self, gc_bases: usize, acgt_bases: usize) {
+        self.gc_bases += gc_bases as u64;
+        self.acgt_bases += acgt_bases as u64;
+        if acgt_bases > 0 {
+            let percent = (gc_bases as f64 / acgt_bases as f64 * 100.0).round() as usize;
+            self.histogram[percent] += 1;
+        }
+    }
+
+    fn merge(&mut self, other: GcStats) {
+        self.gc_bases += other.gc_bases;
+        self.acgt_bases += other.acgt_bases;
+        for (reads, other_reads) in self.histogram.iter_mut().zip(other.histogram) {
+            *reads += other_reads;
+        }
+    }
+
+    /// GC percentage over all the bases of all the reads
+    pub fn overall_gc(&self) -> f64 {
+        if self.acgt_bases == 0 {
+            return 0.0;
+        }
+        self.gc_bases as f64 / self.acgt_bases as f64 * 100.0
+    }
+}
+
+/// Distribution of the proportion of its motifs of the same length that the most frequent motif
+/// of each read makes up: highly repetitive reads make up its upper end
+#[derive(Serialize, Deserialize)]
+pub struct MaxMotifStats {
+    /// Number of reads by percentage, rounded to the nearest integer
+    #[serde(with = "checkpoint::long_array")]
+    histogram: [u64; 101],
+}
+
+impl MaxMotifStats {
+    fn new() -> MaxMotifStats {
+        MaxMotifStats {
+            histogram: [0; 101],
+        }
+    }
+
+    /// Adds the proportion of the most frequent motif of a read, as a fraction
+    fn add_read(&mut self, max_proportion: f64) {
+        let percent = (max_proportion * 100.0).round().min(100.0) as usize;
+        self.histogram[percent] += 1;
+    }
+
+    fn merge(&mut self, other: MaxMotifStats) {
+        for (reads, other_reads) in self.histogram.iter_mut().zip(other.histogram) {
+            *reads += other_reads;
+        }
+    }
+}
+
+/// Homopolymer runs of the analyzed reads
+#[derive(Serialize, Deserialize)]
+pub struct HomopolymerStats {
+    alphabet: Alphabet,
+    /// Number of maximal runs of each length (the index) of each base
+    run_counts: Vec<[u64; 4]>,
+    /// Base and length of the longest run of each read, in the order the reads were selected
+    longest_runs: Vec<Option<(u8, usize)>>,
+}
+
+impl HomopolymerStats {
+    fn new(alphabet: Alphabet) -> HomopolymerStats {
+        HomopolymerStats {
+            alphabet,
+            run_counts: Vec::new(),
+            longest_runs: Vec::new(),
+        }
+    }
+
+    /// Adds the runs of a read, given as base index and length
+    fn add_read(&mut self, runs: &[(usize, usize)]) {
+        for &(base, length) in runs {
+            if self.run_counts.len() <= length {
+                self.run_counts.resize(length + 1, [0; 4]);
+            }
+            self.run_counts[length][base] += 1;
+        }
+        // The first of the longest runs is kept on ties
+        let longest = runs
+            .iter()
+            .rev()
+            .max_by_key(|&&(_, length)| length)
+            .map(|&(base, length)| (self.alphabet.bases()[base], length));
+        self.longest_runs.push(longest);
+    }
+
+    fn merge(&mut self, other: HomopolymerStats) {
+        if self.run_counts.len() < other.run_counts.len() {
+            self.run_counts.resize(other.run_counts.len(), [0; 4]);
+        }
+        for (counts, other_counts) in self.run_counts.iter_mut().zip(other.run_counts) {
+            for (count, other_count) in counts.iter_mut().zip(other_counts) {
+                *count += other_count;
+            }
+        }
+        self.longest_runs.extend(other.longest_runs);
+    }
+}
+
+/// Dinucleotide composition of the analyzed reads
+#[derive(Serialize, Deserialize)]
+pub struct DinucleotideStats {
+    alphabet: Alphabet,
+    /// Number of each dinucleotide, indexed by the index of its first base times 4 plus the
+    /// index of its second base
+    counts: [u64; 16],
+}
+
+impl DinucleotideStats {
+    fn new(alphabet: Alphabet) -> DinucleotideStats {
+        DinucleotideStats {
+            alphabet,
+            counts: [0; 16],
+        }
+    }
+
+    fn add_read(&mut self, counts: &[u64; 16]) {
+        for (count, read_count) in self.counts.iter_mut().zip(counts) {
+            *count += read_count;
+        }
+    }
+
+    fn merge(&mut self, other: DinucleotideStats) {
+        self.add_read(&other.counts);
+    }
+
+    /// Each dinucleotide with its number and its fraction of all the dinucleotides
+    /// (0 when there is none)
+    pub fn composition(&self) -> Vec<(String, u64, f64)> {
+        let total: u64 = self.counts.iter().sum();
+        let bases = self.alphabet.bases();
+        self.counts
+            .iter()
+            .enumerate()
+            .map(|(index, &count)| {
+                let dinucleotide = String::from_utf8(vec![bases[index / 4], bases[index % 4]])
+                    .expect("bases are ASCII");
+                let fraction = if total == 0 {
+                    0.0
+                } else {
+                    count as f64 / total as f64
+                };
+                (dinucleotide, count, fraction)
+            })
+            .collect()
+    }
+}
+
+/// Base composition of the analyzed reads, the bases of the alphabet, N and the other
+/// characters being counted whatever their case
+#[derive(Serialize, Deserialize)]
+pub struct BaseComposition {
+    alphabet: Alphabet,
+    /// Number of each base of the alphabet, then of N, then of the other characters
+    counts: [u64; 6],
+}
+
+impl BaseComposition {
+    fn new(alphabet: Alphabet) -> BaseComposition {
+        BaseComposition {
+            alphabet,
+            counts: [0; 6],
+        }
+    }
+
+    fn add_read(&mut self, counts: &[u64; 6]) {
+        for (count, read_count) in self.counts.iter_mut().zip(counts) {
+            *count += read_count;
+        }
+    }
+
+    fn merge(&mut self, other: BaseComposition) {
+        self.add_read(&other.counts);
+    }
+
+    /// Percentage of N among all the bases (0 when there is none)
+    pub fn n_content(&self) -> f64 {
+        let total: u64 = self.counts.iter().sum();
+        percentage(self.counts[4] as usize, total as usize)
+    }
+
+    /// Each base with its number and its fraction of all the bases (0 when there is none),
+    /// followed by N and the other characters
+    pub fn composition(&self) -> Vec<(String, u64, f64)> {
+        let total: u64 = self.counts.iter().sum();
+        let bases = self
+            .alphabet
+            .bases()
+            .map(|base| char::from(base).to_string());
+        bases
+            .into_iter()
+            .chain(["N".to_string(), "Other".to_string()])
+            .zip(self.counts)
+            .map(|(base, count)| {
+                let fraction = if total == 0 {
+                    0.0
+                } else {
+                    count as f64 / total as f64
+                };
+                (base, count, fraction)
+            })
+            .collect()
+    }
+}
+
+/// Occurrences of the motifs in the regions masked by DUST and in the rest of the reads, to
+/// tell which motifs make up the low-complexity regions. Index 0 is the masked regions and
+/// index 1 the rest
+#[derive(Default, Serialize, Deserialize)]
+pub struct MaskedComposition {
+    /// Number of occurrences of each motif
+    #[serde(with = "checkpoint::mate_pairs")]
+    counts: [HashMap<Motif, u64>; 2],
+    /// Number of windows without ambiguous bases of each motif length
+    windows: [HashMap<usize, u64>; 2],
+}
+
+impl MaskedComposition {
+    /// Counts the motifs of a read in its masked regions, given as half-open intervals that may
+    /// overlap, and in the rest of it. A motif straddling the edge of a region is left out
+    fn add_read(
+        &mut self,
+        sequence: &[u8],
+        intervals: &[(usize, usize)],
+        options: &MotifOptions,
+        motif_lengths: &[usize],
+        panel: Option<&HashSet<Motif>>,
+        counter: &mut MotifCounter,
+    ) {
+        let mut masked = vec![false; sequence.len()];
+        for &(start, end) in intervals {
+            let end = end.min(sequence.len());
+            if start < end {
+                masked[start..end].fill(true);
+            }
+        }
+
+        // Each maximal run of masked, or unmasked, bases is counted on its own
+        let mut start = 0;
+        while start < sequence.len() {
+            let region = if masked[start] { 0 } else { 1 };
+            let end = masked[start..]
+                .iter()
+                .position(|&base_masked| base_masked != masked[start])
+                .map_or(sequence.len(), |length| start + length);
+            let segment = &sequence[start..end];
+            for &k in motif_lengths.iter().filter(|&&k| k <= segment.len()) {
+                let (motif_counts, ambiguous_motifs) =
+                    count_read_motifs(segment, k, 1, options, counter);
+                *self.windows[region].entry(k).or_insert(0) +=
+                    (segment.len() - k + 1 - ambiguous_motifs) as u64;
+                for (motif, count) in motif_counts {
+                    if panel.is_none_or(|panel| panel.contains(&motif)) {
+                        *self.counts[region].entry(motif).or_insert(0) += count;
+                    }
+                }
+            }
+            start = end;
+        }
+    }
+
+    fn merge(&mut self, other: MaskedComposition) {
+        for (counts, other_counts) in self.counts.iter_mut().zip(other.counts) {
+            for (motif, count) in other_counts {
+                *counts.entry(motif).or_insert(0) += count;
+            }
+        }
+        for (windows, other_windows) in self.windows.iter_mut().zip(other.windows) {
+            for (k, count) in other_windows {
+                *windows.entry(k).or_insert(0) += count;
+            }
+        }
+    }
+
+    /// Each motif found, with its number of occurrences and its fraction of the windows of its
+    /// length in the masked regions, then in the rest of the reads, sorted by descending
+    /// fraction in the masked regions, then by motif
+    pub fn composition(&self, options: &MotifOptions) -> Vec<(String, [u64; 2], [f64; 2])> {
+        let motifs: HashSet<&Motif> = self.counts.iter().flat_map(HashMap::keys).collect();
+        let mut composition: Vec<(String, [u64; 2], [f64; 2])> = motifs
+            .into_iter()
+            .map(|motif| {
+                let name = motif.name(options);
+                let counts =
+                    [0, 1].map(|region| self.counts[region].get(motif).copied().unwrap_or(0));
+                let fractions = [0, 1].map(|region| match self.windows[region].get(&name.len()) {
+                    Some(&windows) if windows > 0 => counts[region] as f64 / windows as f64,
+                    _ => 0.0,
+                });
+                (name, counts, fractions)
+            })
+            .collect();
+        composition.sort_by(|a, b| {
+            b.2[0]
+                .partial_cmp(&a.2[0])
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.0.cmp(&b.0))
+        });
+        composition
+    }
+}
+
+/// Counts the bases of the alphabet, N and the other characters of a read, whatever their case
+fn composition_counts(sequence: &[u8], alphabet: Alphabet) -> [u64; 6] {
+    let mut counts = [0; 6];
+    for &base in sequence {
+        let index = match alphabet.index(base) {
+            Some(index) => index,
+            None if base.eq_ignore_ascii_case(&b'N') => 4,
+            None => 5,
+        };
+        counts[index] += 1;
+    }
+    counts
+}
+
+/// Counts the overlapping dinucleotides of the bases of the alphabet, whatever their case
+fn dinucleotide_counts(sequence: &[u8], alphabet: Alphabet) -> [u64; 16] {
+    let mut counts = [0; 16];
+    for pair in sequence.windows(2) {
+        if let (Some(first), Some(second)) = (alphabet.index(pair[0]), alphabet.index(pair[1])) {
+            counts[first * 4 + second] += 1;
+        }
+    }
+    counts
+}
+
+/// Finds the maximal runs of a same base of the alphabet, whatever its case, as base index and
+/// length
+fn homopolymer_runs(sequence: &[u8], alphabet: Alphabet) -> Vec<(usize, usize)> {
+    let mut runs = Vec::new();
+    for run in sequence.chunk_by(|a, b| a.eq_ignore_ascii_case(b)) {
+        if let Some(index) = alphabet.index(run[0]) {
+            runs.push((index, run.len()));
+        }
+    }
+    runs
+}
+
+/// Occurrences of the bases and of the counted motifs over all the analyzed reads
+#[derive(Serialize, Deserialize)]
+pub struct EnrichmentStats {
+    alphabet: Alphabet,
+    /// Number of each base of the alphabet, whatever its case
+    base_counts: [u64; 4],
+    /// Number of occurrences of each motif
+    #[serde(with = "checkpoint::pairs")]
+    motif_occurrences: HashMap<Motif, u64>,
+    /// Number of windows without ambiguous bases of each motif length
+    windows: HashMap<usize, u64>,
+}
+
+impl EnrichmentStats {
+    fn new(alphabet: Alphabet) -> EnrichmentStats {
+        EnrichmentStats {
+            alphabet,
+            base_counts: [0; 4],
+            motif_occurrences: HashMap::new(),
+            windows: HashMap::new(),
+        }
+    }
+
+    fn add_read(&mut self, occurrences: ReadOccurrences) {
+        for (count, read_count) in self.base_counts.iter_mut().zip(occurrences.base_counts) {
+            *count += read_count;
+        }
+        for (motif, count) in occurrences.motifs {
+            *self.motif_occurrences.entry(motif).or_insert(0) += count;
+        }
+        for (k, windows) in occurrences.windows {
+            *self.windows.entry(k).or_insert(0) += windows;
+        }
+    }
+
+    fn merge(&mut self, other: EnrichmentStats) {
+        for (count, other_count) in self.base_counts.iter_mut().zip(other.base_counts) {
+            *count += other_count;
+        }
+        for (motif, count) in other.motif_occurrences {
+            *self.motif_occurrences.entry(motif).or_insert(0) += count;
+        }
+        for (k, windows) in other.windows {
+            *self.windows.entry(k).or_insert(0) += windows;
+        }
+    }
+
+    /// Frequency of a motif expected from the base frequencies, the bases being drawn
+    /// independently. A canonical motif also stands for its reverse complement
+    fn expected_frequency(&self, motif: &str, canonical: bool) -> f64 {
+        let total_bases: u64 = self.base_counts.iter().sum();
+        let frequency = |motif: &[u8]| {
+            motif
+                .iter()
+                .map(|&base| {
+                    self.alphabet.index(base).map_or(0.0, |index| {
+                        self.base_counts[index] as f64 / total_bases as f64
+                    })
+                })
+                .product::<f64>()
+        };
+        if total_bases == 0 {
+            return 0.0;
+        }
+        let motif = motif.as_bytes();
+        let reverse = reverse_complement(motif, self.alphabet);
+        if canonical && reverse != motif {
+            frequency(motif) + frequency(&reverse)
+        } else {
+            frequency(motif)
+        }
+    }
+
+    /// Log2 ratio of the observed frequency of each counted motif over its expected frequency,
+    /// leaving out the motifs never seen or not expected
+    fn log2_enrichment(&self, options: &MotifOptions) -> HashMap<String, f64> {
+        self.motif_occurrences
+            .iter()
+            .filter_map(|(motif, &count)| {
+                let motif = motif.name(options);
+                let windows = self.windows.get(&motif.len()).copied().unwrap_or(0);
+                let expected = self.expected_frequency(&motif, options.canonical);
+                (count > 0 && windows > 0 && expected > 0.0).then(|| {
+                    let observed = count as f64 / windows as f64;
+                    (motif, (observed / expected).log2())
+                })
+            })
+            .collect()
+    }
+}
+
+/// Number of bins of the entropy histogram per bit
+const ENTROPY_BINS_PER_BIT: f64 = 10.0;
+
+/// Distribution of the Shannon entropy of the bases of the analyzed reads
+#[derive(Serialize, Deserialize)]
+pub struct EntropyStats {
+    /// Entropy, in bits, below which a read is counted as low entropy
+    pub min_entropy: f64,
+    /// Number of reads below the minimum entropy
+    pub low_entropy_reads: u64,
+    /// Number of reads with an A/C/G/T base
+    pub reads: u64,
+    /// Number of reads by entropy, in bins of a tenth of a bit (0 to 2 bits)
+    histogram: [u64; 21],
+}
+
+impl EntropyStats {
+    fn new(min_entropy: f64) -> EntropyStats {
+        EntropyStats {
+            min_entropy,
+            low_entropy_reads: 0,
+            reads: 0,
+            histogram: [0; 21],
+        }
+    }
+
+    fn add_read(&mut self, entropy: f64) {
+        self.reads += 1;
+        if entropy < self.min_entropy {
+            self.low_entropy_reads += 1;
+        }
+        let bin = (entropy * ENTROPY_BINS_PER_BIT).round() as usize;
+        self.histogram[bin.min(self.histogram.len() - 1)] += 1;
+    }
+
+    fn merge(&mut self, other: EntropyStats) {
+        self.low_entropy_reads += other.low_entropy_reads;
+        self.reads += other.reads;
+        for (reads, other_reads) in self.histogram.iter_mut().zip(other.histogram) {
+            *reads += other_reads;
+        }
+    }
+
+    /// Percentage of the reads below the minimum entropy
+    pub fn low_entropy_proportion(&self) -> f64 {
+        percentage(self.low_entropy_reads as usize, self.reads as usize)
+    }
+}
+
+/// Shannon entropy, in bits, of the bases of a sequence, unless it has none
+fn base_entropy(sequence: &[u8], alphabet: Alphabet) -> Option<f64> {
+    let counts = base_counts(sequence, alphabet);
+    let total: u64 = counts.iter().sum();
+    (total > 0).then(|| {
+        counts
+            .iter()
+            .filter(|&&count| count > 0)
+            .map(|&count| {
+                let frequency = count as f64 / total as f64;
+                -frequency * frequency.log2()
+            })
+            .sum()
+    })
+}
+
+/// Occurrences of the bases and of the counted motifs in a read
+struct ReadOccurrences {
+    base_counts: [u64; 4],
+    motifs: Vec<(Motif, u64)>,
+    /// Number of windows without ambiguous bases of each motif length
+    windows: Vec<(usize, u64)>,
+}
+
+/// How the progress of the reading is reported. With the position reached in the input, the
+/// number of reads to go through is estimated, the input possibly ending before the maximum
+pub enum Progress {
+    Hidden,
+    /// Logged at debug level every given number of reads, or read pairs, 0 for never
+    Log(usize, Option<InputPosition>),
+    /// Shown on a progress bar, drawn to stderr
+    Bar(ProgressBar, Option<InputPosition>),
+}
+
+impl Progress {
+    /// Progress bar of the reading, going up to the number of reads to analyze when known
+    pub fn bar(position: Option<InputPosition>) -> Progress {
+        Progress::Bar(ProgressBar::new_spinner(), position)
+    }
+
+    /// Sets the bar up for the given maximum number of reads, or read pairs, if any
+    fn start(&self, total: Option<usize>, unit: &'static str) {
+        let (Progress::Log(_, position) | Progress::Bar(_, position)) = self else {
+            return;
+        };
+        if let Some(position) = position {
+            position.mark_start(total);
+        }
+        let Progress::Bar(bar, _) = self else {
+            return;
+        };
+        let template = match (total, position) {
+            (_, Some(_)) => {
+                "{spinner} [{elapsed_precise}] {wide_bar} {pos}/~{len} {msg} ({per_sec}, \
+                 ETA ~{eta}, estimated from the input size)"
+            }
+            (Some(total), None) => {
+                bar.set_length(total as u64);
+                "{spinner} [{elapsed_precise}] {wide_bar} {pos}/{len} {msg} ({per_sec}, ETA {eta})"
+            }
+            (None, None) => "{spinner} [{elapsed_precise}] {pos} {msg} ({per_sec})",
+        };
+        if let Ok(style) = ProgressStyle::with_template(template) {
+            bar.set_style(style);
+        }
+        bar.set_message(unit);
+    }
+
+    /// Reports the number of reads, or read pairs, processed or scanned so far
+    fn update(&self, count: usize, action: &str, unit: &str) {
+        match self {
+            Progress::Hidden => {}
+            Progress::Log(interval, position) => {
+                if *interval == 0 || !count.is_multiple_of(*interval) {
+                    return;
+                }
+                match position.as_ref().and_then(|p| p.estimated_total(count)) {
+                    Some(total) => debug!(
+                        "{} {} {} (about {:.0}%, estimated from the input size)...",
+                        action,
+                        count,
+                        unit,
+                        percentage(count, total)
+                    ),
+                    None => debug!("{} {} {}...", action, count, unit),
+                }
+            }
+            Progress::Bar(bar, position) => {
+                // The estimate is refined as the input is read
+                if let Some(total) = position.as_ref().and_then(|p| p.estimated_total(count)) {
+                    bar.set_length(total as u64);
+                }
+                bar.set_position(count as u64);
+            }
+        }
+    }
+
+    /// Removes the bar, before the summary of the reading is logged
+    fn finish(&self) {
+        if let Progress::Bar(bar, _) = self {
+            bar.finish_and_clear();
+        }
+    }
+}
+
+/// How the analyzed reads are picked from the input
+#[derive(Debug, Clone, Copy)]
+pub enum ReadSelection {
+    /// The first reads following the skipped ones
+    First(usize),
+    /// A uniform random sample of the reads following the skipped ones
+    Random { reads: usize, seed: Option<u64> },
+}
+
+/// A read retained for analysis, restricted to the part used for motif counting
+struct SelectedRead {
+    sequence: Vec<u8>,
+    /// Length of the full read, once trimmed
+    length: usize,
+    /// 0 for single-end reads and first mates, 1 for second mates
+    mate: usize,
+    /// Mean Phred quality of the full read, unless it has no qualities
+    mean_quality: Option<f64>,
+    /// `lane:tile` label of the read, when the counts are broken down by tile
+    tile: Option<String>,
+    /// Name of the read, when the names are kept
+    name: Option<String>,
+    /// Bases of the full read, once trimmed, as counted by [`composition_counts`]
+    base_counts: [u64; 6],
+}
+
+/// Motif counts of each mate of paired-end reads
+#[derive(Serialize, Deserialize)]
+pub struct MateCounts {
+    #[serde(with = "checkpoint::mate_pairs")]
+    pub motif_counts: [HashMap<Motif, u64>; 2],
+    /// Mate of each read, in the order the reads were selected
+    pub mates: Vec<u8>,
+}
+
+impl MateCounts {
+    fn new() -> MateCounts {
+        MateCounts {
+            motif_counts: [HashMap::new(), HashMap::new()],
+            mates: Vec::new(),
+        }
+    }
+
+    fn merge(&mut self, other: MateCounts) {
+        for (counts, other_counts) in self.motif_counts.iter_mut().zip(other.motif_counts) {
+            for (motif, count) in other_counts {
+                *counts.entry(motif).or_insert(0) += count;
+            }
+        }
+        self.mates.extend(other.mates);
+    }
+}
+
+/// Accumulates the statistics of the selected reads
+struct ReadSink<'a, 'w> {
+    options: &'a MotifOptions,
+    /// Lengths of the counted motifs
+    motif_lengths: Vec<usize>,
+    /// Motifs of the panel, when only those are counted
+    panel: Option<HashSet<Motif>>,
+    fasta_writer: Option<&'w mut dyn Write>,
+    chunk: Vec<SelectedRead>,
+    stats: ReadStats,
+    /// Whether the counts were found to take more than the maximum memory, warned about once
+    memory_exceeded: bool,
+}
+
+impl ReadSink<'_, '_> {
+    /// Records a selected read, counting the motifs of the pending reads once a chunk is full
+    fn add(&mut self, mut read: SelectedRead) -> Result<(), AppError> {
+        let fasta_error = |e: io::Error| AppError::Output(format!("Failed to write FASTA: {}", e));
+
+        self.stats.total_reads += 1;
+
+        // Write to FASTA for SDUST, naming the reads by their index to keep the lookup cheap.
+        // The names in the input are never used, so reads sharing one, as merged or
+        // demultiplexed files may have, each keep their own masked bases
+        if let Some(writer) = self.fasta_writer.as_mut() {
+            writeln!(writer, ">{}", self.stats.read_lengths.len()).map_err(fasta_error)?;
+            writer.write_all(&read.sequence).map_err(fasta_error)?;
+            writeln!(writer).map_err(fasta_error)?;
+        }
+        self.stats.read_lengths.push(read.length);
+        if self.options.weight == Weight::ByBase {
+            self.stats.analyzed_lengths.push(read.sequence.len());
+        }
+        if let Some(name) = read.name.take() {
+            self.stats.read_names.push(name);
+        }
+        self.stats.base_composition.add_read(&read.base_counts);
+        if let Some(mean_quality) = read.mean_quality {
+            self.stats.quality_sum += mean_quality;
+            self.stats.quality_reads += 1;
+        }
+        if let Some(mate_counts) = self.stats.mate_counts.as_mut() {
+            mate_counts.mates.push(read.mate as u8);
+        }
+
+        self.chunk.push(read);
+        if self.chunk.len() == CHUNK_SIZE {
+            self.count_chunk();
+        }
+        Ok(())
+    }
+
+    /// Counts the motifs of the pending reads in parallel and merges them into the running totals
+    fn count_chunk(&mut self) {
+        let native_dust = self.fasta_writer.is_none() || self.options.dust_intervals;
+        let analyses: Vec<ReadAnalysis> = self
+            .chunk
+            .par_iter()
+            .map_init(MotifCounter::default, |counter, read| {
+                analyze_read(
+                    &read.sequence,
+                    self.options,
+                    &self.motif_lengths,
+                    self.panel.as_ref(),
+                    native_dust,
+                    counter,
+                )
+            })
+            .collect();
+
+        for (read, analysis) in self.chunk.drain(..).zip(analyses) {
+            if let Some(max_proportion) = analysis.max_proportion {
+                self.stats.max_motif_stats.add_read(max_proportion);
+            }
+            if let Some(gc_stats) = self.stats.gc_stats.as_mut() {
+                gc_stats.add_read(analysis.gc_bases, analysis.acgt_bases);
+            }
+            if let Some(homopolymer_stats) = self.stats.homopolymer_stats.as_mut() {
+                homopolymer_stats.add_read(&analysis.homopolymer_runs);
+            }
+            if let (Some(masked_composition), Some(composition)) = (
+                self.stats.masked_composition.as_mut(),
+                analysis.masked_composition,
+            ) {
+                masked_composition.merge(composition);
+            }
+            if let (Some(dinucleotide_stats), Some(counts)) = (
+                self.stats.dinucleotide_stats.as_mut(),
+                &analysis.dinucleotide_counts,
+            ) {
+                dinucleotide_stats.add_read(counts);
+            }
+            if let (Some(enrichment_stats), Some(occurrences)) =
+                (self.stats.enrichment_stats.as_mut(), analysis.occurrences)
+            {
+                enrichment_stats.add_read(occurrences);
+            }
+            if let (Some(entropy_stats), Some(entropy)) =
+                (self.stats.entropy_stats.as_mut(), analysis.entropy)
+            {
+                entropy_stats.add_read(entropy);
+            }
+            if let Some(bins) = &self.options.length_bins {
+                let bin = bins.label(read.length);
+                for motif in &analysis.motifs {
+                    *self
+                        .stats
+                        .binned_counts
+                        .entry((bin.clone(), motif.clone()))
+                        .or_insert(0) += 1;
+                }
+            }
+            if let Some(tile) = read.tile {
+                for motif in &analysis.motifs {
+                    *self
+                        .stats
+                        .tile_counts
+                        .entry((tile.clone(), motif.clone()))
+                        .or_insert(0) += 1;
+                }
+                self.stats.read_tiles.push(tile);
+            }
+            if let Some(mate_counts) = self.stats.mate_counts.as_mut() {
+                for motif in &analysis.motifs {
+                    *mate_counts.motif_counts[read.mate]
+                        .entry(motif.clone())
+                        .or_insert(0) += 1;
+                }
+            }
+            let weight = match self.options.weight {
+                Weight::ByRead => 1,
+                Weight::ByBase => read.sequence.len(),
+            };
+            for motif in analysis.motifs {
+                *self.stats.motif_counts.entry(motif).or_insert(0) += weight as u64;
+            }
+            let keep_frequencies = self
+                .options
+                .frequency_stat()
+                .is_some_and(AggregateStat::needs_read_frequencies);
+            for (motif, frequency) in analysis.frequencies {
+                if keep_frequencies {
+                    self.stats
+                        .read_frequencies
+                        .entry(motif.clone())
+                        .or_default()
+                        .push(frequency);
+                }
+                *self.stats.frequency_sums.entry(motif).or_insert(0.0) += frequency * weight as f64;
+            }
+            if !self.options.position_motifs.is_empty() {
+                let position_reads = &mut self.stats.position_reads;
+                if position_reads.len() < read.sequence.len() {
+                    position_reads.resize(read.sequence.len(), 0);
+                }
+                for reads in &mut position_reads[..read.sequence.len()] {
+                    *reads += 1;
+                }
+                for (motif, position) in analysis.motif_positions {
+                    let counts = &mut self.stats.position_counts[motif];
+                    if counts.len() <= position {
+                        counts.resize(position + 1, 0);
+                    }
+                    counts[position] += 1;
+                }
+            }
+            self.stats.masked_bases.push(analysis.masked);
+            if let Some(intervals) = analysis.dust_intervals {
+                self.stats.dust_intervals.push(intervals);
+            }
+        }
+
+        if let Some(max_memory) = self.options.max_memory {
+            let memory = self.stats.estimated_memory();
+            if !self.memory_exceeded && memory > max_memory {
+                self.memory_exceeded = true;
+                warn!(
+                    "the counts take about {} MB after {} reads, above --max-memory {} MB: \
+                     analyze fewer reads (--max-reads, --random) or count fewer motif lengths",
+                    memory >> 20,
+                    self.stats.total_reads,
+                    max_memory >> 20
+                );
+            }
+        }
+    }
+
+    /// Counts the remaining reads and returns the accumulated statistics
+    fn finish(mut self) -> ReadStats {
+        self.count_chunk();
+        self.stats
+    }
+}
+
+/// Sequence of a FASTQ or FASTA record, with its quality string for FASTQ
+struct SequenceRecord {
+    /// Header, without its leading `@` or `>`
+    name: Vec<u8>,
+    sequence: Vec<u8>,
+    quality: Option<Vec<u8>>,
+}
+
+/// Reads FASTQ or FASTA records from raw byte lines.
+/// Sequences and qualities may span several lines, and malformed records are skipped.
+struct SequenceRecords<R> {
+    lines: io::Split<R>,
+    /// Format of the records, resolved from the first line when detected
+    format: InputFormat,
+    /// Line read past the end of a record, starting the next one
+    pending: Option<Vec<u8>>,
+    /// Number of malformed records skipped so far
+    malformed: usize,
+    /// Number of the malformed records whose quality length differs from their sequence length
+    quality_mismatches: usize,
+    /// Fail on those records instead of skipping them
+    validate_quality_length: bool,
+}
+
+impl<R: BufRead> SequenceRecords<R> {
+    fn new(reader: R, format: InputFormat) -> SequenceRecords<R> {
+        SequenceRecords {
+            lines: reader.split(b'\n'),
+            format,
+            pending: None,
+            malformed: 0,
+            quality_mismatches: 0,
+            validate_quality_length: false,
+        }
+    }
+
+    // Lines are read as raw bytes so that a corrupted sequence does not abort the whole run.
+    // Trailing whitespace, such as the '\r' of CRLF line endings, is stripped
+    fn next_line(&mut self) -> Result<Option<Vec<u8>>, AppError> {
+        if let Some(line) = self.pending.take() {
+            return Ok(Some(line));
+        }
+        let mut line = self
+            .lines
+            .next()
+            .transpose()
+            .map_err(|e| AppError::Input(format!("Failed to read input: {}", e)))?;
+        if let Some(line) = line.as_mut() {
+            while line.last().is_some_and(u8::is_ascii_whitespace) {
+                line.pop();
+            }
+        }
+        Ok(line)
+    }
+
+    /// Skips lines up to the next one that looks like a record header
+    fn resync(&mut self, marker: u8) -> Result<(), AppError> {
+        self.malformed += 1;
+        while let Some(line) = self.next_line()? {
+            if line.first() == Some(&marker) {
+                self.pending = Some(line);
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the next well-formed record, or `None` at the end of the input
+    fn next_record(&mut self) -> Result<Option<SequenceRecord>, AppError> {
+        if self.format == InputFormat::Auto {
+            // FASTA records start with '>', anything else is read as FASTQ
+            let first = loop {
+                match self.next_line()? {
+                    Some(line) if line.is_empty() => continue,
+                    line => break line,
+                }
+            };
+            self.format = match first.as_deref() {
+                Some([b'>', ..]) => InputFormat::Fasta,
+                _ => InputFormat::Fastq,
+            };
+            self.pending = first;
+        }
+        match self.format {
+            InputFormat::Fasta => self.next_fasta_record(),
+            _ => self.next_fastq_record(),
+        }
+    }
+
+    fn next_fasta_record(&mut self) -> Result<Option<SequenceRecord>, AppError> {
+        let header = loop {
+            match self.next_line()? {
+                Some(line) if line.starts_with(b">") => break line,
+                Some(line) if line.is_empty() => continue,
+                Some(_) => self.resync(b'>')?,
+                None => return Ok(None),
+            }
+        };
+
+        // The sequence runs up to the next header
+        let mut sequence = Vec::new();
+        while let Some(line) = self.next_line()? {
+            if line.starts_with(b">") {
+                self.pending = Some(line);
+                break;
+            }
+            sequence.extend_from_slice(&line);
+        }
+        Ok(Some(SequenceRecord {
+            name: header[1..].to_vec(),
+            sequence,
+            quality: None,
+        }))
+    }
+
+    fn next_fastq_record(&mut self) -> Result<Option<SequenceRecord>, AppError> {
+        'records: loop {
+            let header = match self.next_line()? {
+                Some(line) if line.is_empty() => continue,
+                Some(line) => line,
+                None => return Ok(None),
+            };
+            if !header.starts_with(b"@") {
+                self.resync(b'@')?;
+                continue;
+            }
+
+            // The sequence runs up to the '+' separator
+            let mut sequence = Vec::new();
+            let separator = loop {
+                match self.next_line()? {
+                    Some(line) if line.starts_with(b"+") => break line,
+                    Some(line) if line.starts_with(b"@") => {
+                        // Header of the next record, this one has no separator
+                        self.pending = Some(line);
+                        self.malformed += 1;
+                        continue 'records;
+                    }
+                    Some(line) => sequence.extend_from_slice(&line),
+                    None => {
+                        self.malformed += 1;
+                        return Ok(None);
+                    }
+                }
+            };
+
+            // The separator may repeat the header
+            if separator.len() > 1 && !same_record_id(&header[1..], &separator[1..]) {
+                self.resync(b'@')?;
+                continue;
+            }
+
+            // The quality has as many characters as the sequence, possibly over several lines
+            let mut quality = Vec::with_capacity(sequence.len());
+            let mut truncated = false;
+            while quality.len() < sequence.len() {
+                match self.next_line()? {
+                    Some(line) => quality.extend_from_slice(&line),
+                    None => {
+                        truncated = true;
+                        break;
+                    }
+                }
+            }
+            if quality.len() != sequence.len() {
+                self.quality_mismatches += 1;
+                if self.validate_quality_length {
+                    return Err(AppError::Input(format!(
+                        "Quality of {} characters for a sequence of {} bases in record {}{}",
+                        quality.len(),
+                        sequence.len(),
+                        String::from_utf8_lossy(&header[1..]),
+                        if truncated { " (truncated input)" } else { "" }
+                    )));
+                }
+                if truncated {
+                    self.malformed += 1;
+                    return Ok(None);
+                }
+                self.resync(b'@')?;
+                continue;
+            }
+
+            return Ok(Some(SequenceRecord {
+                name: header[1..].to_vec(),
+                sequence,
+                quality: Some(quality),
+            }));
+        }
+    }
+}
+
+/// Mean Phred score of a quality string, 0 for an empty one
+fn mean_quality(quality: &[u8], phred_offset: u8) -> f64 {
+    if quality.is_empty() {
+        return 0.0;
+    }
+    let total: u64 = quality
+        .iter()
+        .map(|&score| score.saturating_sub(phred_offset) as u64)
+        .sum();
+    total as f64 / quality.len() as f64
+}
+
+/// Whether the text of a '+' separator names the same record as the header
+fn same_record_id(header: &[u8], separator: &[u8]) -> bool {
+    let id = |line: &[u8]| {
+        line.split(u8::is_ascii_whitespace)
+            .next()
+            .map(<[u8]>::to_vec)
+    };
+    header == separator || id(header) == id(separator)
+}
+
+/// Counts the well-formed records of a FASTQ or FASTA input, for a first pass over it
+pub fn count_records<R: BufRead>(reader: R, format: InputFormat) -> Result<usize, AppError> {
+    let mut records = SequenceRecords::new(reader, format);
+    let mut count = 0;
+    while records.next_record()?.is_some() {
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Processes reads and counts motifs.
+/// When a FASTA writer is given, reads are written to it for the external SDUST, named by
+/// their index whatever their name in the input, otherwise their low-complexity regions are
+/// masked with the built-in DUST.
+/// With a second reader, both mates of paired-end reads are read in lockstep and
+/// `selection` and `skip_reads` count read pairs, as they do for interleaved reads.
+pub fn process_reads_and_write_fasta<R: BufRead>(
+    reader: R,
+    reader2: Option<R>,
+    fasta_writer: Option<&mut dyn Write>,
+    selection: ReadSelection,
+    skip_reads: usize,
+    progress: &Progress,
+    options: &MotifOptions,
+) -> Result<ReadStats, AppError> {
+    if options.interleaved && reader2.is_some() {
+        return Err(AppError::Input(
+            "Interleaved reads have no file of second mates".to_string(),
+        ));
+    }
+    let paired = reader2.is_some() || options.interleaved;
+    let mut sink = ReadSink {
+        options,
+        motif_lengths: motif_lengths(options),
+        panel: motif_panel(options).map(|panel| {
+            panel
+                .iter()
+                .map(|motif| Motif::from_name(motif, options))
+                .collect()
+        }),
+        fasta_writer,
+        chunk: Vec::with_capacity(CHUNK_SIZE),
+        stats: ReadStats::new(options, paired),
+        memory_exceeded: false,
+    };
+
+    let mut records = SequenceRecords::new(reader, options.input_format);
+    let mut records2 = reader2.map(|reader2| SequenceRecords::new(reader2, options.input_format));
+    for records in std::iter::once(&mut records).chain(records2.as_mut()) {
+        records.validate_quality_length = options.validate_quality_length;
+    }
+    let mut invalid_reads = 0;
+    let mut low_quality_reads = 0;
+    let mut long_reads = 0;
+    let mut unmatched_reads = 0;
+    // Shared with the checkpoints, saved while the reads are being selected
+    let examined_reads = Cell::new(0);
+    let short_reads = Cell::new(0);
+    let records_read = Cell::new(0);
+
+    // Returns the next read, or both mates of the next pair
+    let mut next_input_records = || -> Result<Option<Vec<SequenceRecord>>, AppError> {
+        let record = records.next_record()?;
+        if options.interleaved {
+            let Some(record) = record else {
+                return Ok(None);
+            };
+            return match records.next_record()? {
+                Some(record2) => Ok(Some(vec![record, record2])),
+                None => Err(AppError::Input(
+                    "The interleaved input has an odd number of reads".to_string(),
+                )),
+            };
+        }
+        let Some(records2) = records2.as_mut() else {
+            return Ok(record.map(|record| vec![record]));
+        };
+        match (record, records2.next_record()?) {
+            (Some(record), Some(record2)) => Ok(Some(vec![record, record2])),
+            (None, None) => Ok(None),
+            _ => Err(AppError::Input(
+                "The two input files have different numbers of reads".to_string(),
+            )),
+        }
+    };
+
+    let mut next_records = || -> Result<Option<Vec<SequenceRecord>>, AppError> {
+        let records = next_input_records()?;
+        if records.is_some() {
+            records_read.set(records_read.get() + 1);
+        }
+        Ok(records)
+    };
+
+    // A resumed run goes on after the records read up to the checkpoint
+    let mut resumed_reads = 0;
+    let mut skipped_records = skip_reads;
+    let fingerprint = checkpoint::fingerprint(options, selection);
+    let checkpoint = match &options.checkpoint {
+        Some(checkpoint) => checkpoint::load(&checkpoint.path)?,
+        None => None,
+    };
+    if let (Some(checkpoint), Some(checkpoint_options)) = (checkpoint, &options.checkpoint) {
+        checkpoint.check(checkpoint_options, skip_reads, &fingerprint)?;
+        info!(
+            "Resuming from the checkpoint, after {} analyzed reads",
+            checkpoint.stats.total_reads
+        );
+        resumed_reads = checkpoint.selected;
+        skipped_records = checkpoint.records;
+        examined_reads.set(checkpoint.examined_reads);
+        short_reads.set(checkpoint.stats.short_reads);
+        sink.stats = checkpoint.stats;
+    }
+
+    // Skip the first `skip_reads` reads
+    for skipped in 0..skipped_records {
+        if next_records()?.is_none() {
+            if skipped >= skip_reads {
+                return Err(AppError::Input(
+                    "The input has fewer reads than when the checkpoint was saved".to_string(),
+                ));
+            }
+            warn!(
+                "the input only has {} reads, all of them skipped by --skip {}",
+                skipped, skip_reads
+            );
+            break;
+        }
+    }
+
+    // Length to take into account
+    let processing_length = 150;
+    // Minimal length to take into account 150bp from position (1000-150) instead of first position
+    let sequence_length_threshold = 1000;
+
+    // Returns the read if it passes the filters
+    let mut select_read = |record: SequenceRecord, mate: usize| -> Option<SelectedRead> {
+        let SequenceRecord {
+            name,
+            mut sequence,
+            mut quality,
+        } = record;
+        examined_reads.set(examined_reads.get() + 1);
+
+        if let Some(name_filter) = &options.name_filter {
+            if !name_filter.is_match(&name) {
+                unmatched_reads += 1;
+                return None;
+            }
+        }
+        let tile = options
+            .by_tile
+            .then(|| illumina_tile(&name).unwrap_or_else(|| UNKNOWN_TILE.to_string()));
+        let read_name = options
+            .read_names
+            .then(|| String::from_utf8_lossy(&name).into_owned());
+
+        // Trimmed bases are left out of the analysis altogether, including the read length
+        let kept = sequence
+            .len()
+            .saturating_sub(options.trim_start + options.trim_end);
+        for bases in std::iter::once(&mut sequence).chain(quality.as_mut()) {
+            bases.drain(..options.trim_start.min(bases.len()));
+            bases.truncate(kept);
+        }
+
+        // Long reads are capped before anything else is done with them
+        if let Some(max_length) = options.max_read_length {
+            if sequence.len() > max_length {
+                long_reads += 1;
+                if options.long_reads == LongReads::Skip {
+                    return None;
+                }
+                sequence.truncate(max_length);
+                sequence.shrink_to_fit();
+                if let Some(quality) = quality.as_mut() {
+                    quality.truncate(max_length);
+                }
+            }
+        }
+
+        if !options.preserve_case {
+            sequence.make_ascii_uppercase();
+        }
+
+        let length = sequence.len();
+        if length < options.min_read_length {
+            short_reads.set(short_reads.get() + 1);
+            return None;
+        }
+
+        // Only letters are expected in a sequence line
+        if !sequence.iter().all(u8::is_ascii_alphabetic) {
+            invalid_reads += 1;
+            return None;
+        }
+
+        let mean_quality = quality.map(|quality| mean_quality(&quality, options.phred_offset));
+        if mean_quality
+            .zip(options.min_mean_quality)
+            .is_some_and(|(mean_quality, min_quality)| mean_quality < min_quality)
+        {
+            low_quality_reads += 1;
+            return None;
+        }
+
+        // The composition covers the full reads, not only their analyzed part
+        let base_counts = composition_counts(&sequence, options.alphabet);
+
+        // Adjust the sequence based on its length
+        if length > processing_length {
+            if length < sequence_length_threshold {
+                sequence.truncate(processing_length);
+            } else {
+                sequence.truncate(sequence_length_threshold);
+                sequence.drain(..sequence_length_threshold - processing_length);
+            }
+        }
+
+        Some(SelectedRead {
+            sequence,
+            length,
+            mate,
+            mean_quality,
+            tile,
+            name: read_name,
+            base_counts,
+        })
+    };
+
+    // Returns the next read, or pair of mates, of which at least one read passes the filters
+    let mut next_reads = || -> Result<Option<Vec<SelectedRead>>, AppError> {
+        while let Some(records) = next_records()? {
+            let reads: Vec<SelectedRead> = records
+                .into_iter()
+                .enumerate()
+                .filter_map(|(mate, record)| select_read(record, mate))
+                .collect();
+            if !reads.is_empty() {
+                return Ok(Some(reads));
+            }
+        }
+        Ok(None)
+    };
+    let unit = if paired { "read pairs" } else { "reads" };
+
+    match selection {
+        ReadSelection::First(max_reads) => {
+            progress.start((max_reads != usize::MAX).then_some(max_reads), unit);
+            let mut last_checkpoint = Instant::now();
+            for selected in resumed_reads + 1..=max_reads {
+                match next_reads()? {
+                    Some(reads) => reads.into_iter().try_for_each(|read| sink.add(read))?,
+                    None => break,
+                }
+                progress.update(selected, "Processed", unit);
+
+                if let Some(checkpoint) = options
+                    .checkpoint
+                    .as_ref()
+                    .filter(|checkpoint| last_checkpoint.elapsed() >= checkpoint.interval)
+                {
+                    // Pending reads are counted for the checkpoint to hold all the reads read
+                    sink.count_chunk();
+                    sink.stats.short_reads = short_reads.get();
+                    checkpoint::save(
+                        checkpoint,
+                        &fingerprint,
+                        skip_reads,
+                        records_read.get(),
+                        selected,
+                        examined_reads.get(),
+                        &sink.stats,
+                    )?;
+                    debug!("Checkpoint saved after {} {}", selected, unit);
+                    last_checkpoint = Instant::now();
+                }
+            }
+            progress.finish();
+        }
+        ReadSelection::Random { reads, seed } => {
+            // Reservoir sampling over the whole input
+            let mut rng = match seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_entropy(),
+            };
+            let mut reservoir: Vec<Vec<SelectedRead>> = Vec::with_capacity(reads);
+            let mut seen: usize = 0;
+            progress.start(None, unit);
+            while let Some(selected) = next_reads()? {
+                seen += 1;
+                progress.update(seen, "Scanned", unit);
+                if reservoir.len() < reads {
+                    reservoir.push(selected);
+                } else {
+                    let slot = rng.gen_range(0..seen);
+                    if slot < reads {
+                        reservoir[slot] = selected;
+                    }
+                }
+            }
+            progress.finish();
+            info!("Sampled {} of {} {}", reservoir.len(), seen, unit);
+            for read in reservoir.into_iter().flatten() {
+                sink.add(read)?;
+            }
+        }
+    }
+
+    let malformed = records.malformed + records2.as_ref().map_or(0, |records| records.malformed);
+    let quality_mismatches = records.quality_mismatches
+        + records2
+            .as_ref()
+            .map_or(0, |records| records.quality_mismatches);
+    if quality_mismatches > 0 {
+        warn!(
+            "skipped {} malformed FASTQ records, {} of them with a quality length differing from \
+             their sequence length",
+            malformed, quality_mismatches
+        );
+    } else if malformed > 0 {
+        warn!("skipped {} malformed FASTQ records", malformed);
+    }
+    if invalid_reads > 0 {
+        warn!(
+            "skipped {} reads containing characters other than letters",
+            invalid_reads
+        );
+    }
+    if let Some(min_quality) = options.min_mean_quality {
+        info!(
+            "Skipped {} reads with a mean quality below {}",
+            low_quality_reads, min_quality
+        );
+    }
+    if let Some(name_filter) = &options.name_filter {
+        info!(
+            "Skipped {} reads whose name does not match {}",
+            unmatched_reads, name_filter
+        );
+    }
+    if let Some(max_length) = options.max_read_length {
+        let action = match options.long_reads {
+            LongReads::Truncate => "Truncated",
+            LongReads::Skip => "Skipped",
+        };
+        info!(
+            "{} {} reads longer than {} bases",
+            action, long_reads, max_length
+        );
+    }
+    if let Some(writer) = sink.fasta_writer.as_mut() {
+        writer
+            .flush()
+            .map_err(|e| AppError::Output(format!("Failed to write FASTA: {}", e)))?;
+    }
+    let (short_reads, examined_reads) = (short_reads.get(), examined_reads.get());
+    if short_reads > 0 {
+        let fraction = short_reads as f64 / examined_reads as f64;
+        let message = format!(
+            "Skipped {} reads shorter than {} bases ({:.2}% of the reads)",
+            short_reads,
+            options.min_read_length,
+            fraction * 100.0
+        );
+        if fraction > MAX_SHORT_READ_FRACTION {
+            warn!("{}, check the input and the trimming", message);
+        } else {
+            info!("{}", message);
+        }
+    }
+    let mut stats = sink.finish();
+    stats.short_reads = short_reads;
+    info!("Total reads processed: {}", stats.total_reads);
+    let duplicates = duplicate_ids(&stats.read_names);
+    if duplicates > 0 {
+        warn!(
+            "{} reads share their id with an earlier read, and are told apart by their order only in the per-read outputs",
+            duplicates
+        );
+    }
+    Ok(stats)
+}
+
+/// Number of the reads whose id was already taken by an earlier read
+fn duplicate_ids(read_names: &[String]) -> usize {
+    let mut ids = HashSet::new();
+    read_names
+        .iter()
+        .filter(|name| !ids.insert(read_id(name)))
+        .count()
+}
+
+/// Returns the id of a read, its name up to the first whitespace
+fn read_id(name: &str) -> &str {
+    name.split_whitespace().next().unwrap_or_default()
+}
+
+/// Label of the reads whose name does not give their Illumina tile
+pub const UNKNOWN_TILE: &str = "unknown";
+
+/// Returns the `lane:tile` label of a read from its name, if it follows the Illumina format,
+/// `instrument:run:flowcell:lane:tile:x:y` or the older `instrument:lane:tile:x:y`
+fn illumina_tile(name: &[u8]) -> Option<String> {
+    let name = String::from_utf8_lossy(name);
+    let id = name.split_whitespace().next()?;
+    let fields: Vec<&str> = id.split(':').collect();
+    let (lane, tile) = match fields.len() {
+        7 => (fields[3], fields[4]),
+        5 => (fields[1], fields[2]),
+        _ => return None,
+    };
+    let is_number = |field: &str| !field.is_empty() && field.bytes().all(|b| b.is_ascii_digit());
+    (is_number(lane) && is_number(tile)).then(|| format!("{}:{}", lane, tile))
+}
+
+/// Motif and low-complexity statistics of the reads of a read-length bin, or of a tile
+pub struct LengthBinStats {
+    pub label: String,
+    /// Number of reads in the bin
+    pub total_reads: usize,
+    /// Percentage of the reads of the bin in which each motif exceeds the minimum proportion,
+    /// and percentage of low-complexity reads, sorted by descending value
+    pub proportions: Vec<(String, f64)>,
+    /// Number of the reads of the bin in which each motif exceeds the minimum proportion, and
+    /// number of low-complexity reads
+    pub counts: HashMap<String, u64>,
+}
+
+/// Motif, low-complexity and read statistics of the analyzed reads
+pub struct MotifStats {
+    /// Number of analyzed reads
+    pub total_reads: usize,
+    /// Percentage of reads in which each motif exceeds the minimum proportion, and percentage
+    /// of low-complexity reads, sorted by descending value
+    pub proportions: Vec<(String, f64)>,
+    /// Number of reads in which each motif exceeds the minimum proportion, and number of
+    /// low-complexity reads
+    pub counts: HashMap<String, u64>,
+    /// Percentage of low-complexity reads
+    pub low_complexity_proportion: f64,
+    /// Statistics of each read-length bin, empty unless bins are set
+    pub length_bins: Vec<LengthBinStats>,
+    /// Statistics of each tile, labelled `lane:tile`, empty unless broken down by tile
+    pub tiles: Vec<LengthBinStats>,
+    /// Proportions of each mate, for paired-end reads
+    pub mate_proportions: Option<[HashMap<String, f64>; 2]>,
+    /// Summary of the full read lengths
+    pub read_lengths: LengthSummary,
+    /// Overall GC percentage, when computed
+    pub gc_content: Option<f64>,
+    /// Mean of the mean Phred qualities of the reads, unless none has qualities
+    pub mean_quality: Option<f64>,
+    /// Position, motif and fraction of the reads covering the motif at that position in
+    /// which it occurs, for each profiled motif
+    pub position_profile: Vec<(usize, String, f64)>,
+    /// Log2 ratio of the observed over the expected frequency of each motif, when computed.
+    /// Motifs never seen are left out
+    pub enrichment: Option<HashMap<String, f64>>,
+}
+
+impl MotifStats {
+    /// Motifs, including LowComplexity, found in more than `ratio` percent of the reads,
+    /// sorted by descending proportion
+    pub fn flagged_motifs(&self, ratio: f64) -> Vec<(String, f64)> {
+        self.proportions
+            .iter()
+            .filter(|(_, proportion)| *proportion > ratio)
+            .cloned()
+            .collect()
+    }
+
+    /// Keeps the `top` motifs of highest proportion, and LowComplexity, in the proportions over
+    /// all the reads and in those of the read-length bins
+    pub fn keep_top_motifs(&mut self, top: usize) {
+        let mut kept = 0;
+        self.proportions.retain(|(motif, _)| {
+            if motif != LOW_COMPLEXITY_MOTIF {
+                kept += 1;
+            }
+            motif == LOW_COMPLEXITY_MOTIF || kept <= top
+        });
+        let kept: HashSet<&String> = self.proportions.iter().map(|(motif, _)| motif).collect();
+        for bin in self.length_bins.iter_mut().chain(&mut self.tiles) {
+            bin.proportions.retain(|(motif, _)| kept.contains(motif));
+        }
+    }
+}
+
+/// Analyzes all the reads of a FASTQ reader, masking their low-complexity regions with the
+/// built-in DUST
+pub fn analyze_reads<R: BufRead>(
+    reader: R,
+    options: &MotifOptions,
+) -> Result<MotifStats, AppError> {
+    let read_stats = process_reads_and_write_fasta(
+        reader,
+        None,
+        None,
+        ReadSelection::First(usize::MAX),
+        0,
+        &Progress::Hidden,
+        options,
+    )?;
+    if read_stats.total_reads == 0 {
+        return Err(AppError::Input("No reads analyzed".to_string()));
+    }
+    Ok(compute_motif_stats(&read_stats, options))
+}
+
+/// Computes the proportions of the motifs and of the low-complexity reads among processed reads
+/// (all 0 when no read was processed)
+pub fn compute_motif_stats(read_stats: &ReadStats, options: &MotifOptions) -> MotifStats {
+    let ReadStats {
+        motif_counts,
+        total_reads,
+        read_lengths,
+        masked_bases,
+        analyzed_lengths,
+        binned_counts,
+        tile_counts,
+        read_tiles,
+        read_names: _,
+        dust_intervals: _,
+        gc_stats,
+        mate_counts,
+        quality_sum,
+        quality_reads,
+        short_reads: _,
+        position_counts,
+        position_reads,
+        homopolymer_stats: _,
+        enrichment_stats,
+        entropy_stats: _,
+        dinucleotide_stats: _,
+        base_composition: _,
+        masked_composition: _,
+        max_motif_stats: _,
+        frequency_sums,
+        read_frequencies,
+    } = read_stats;
+    let total_reads = *total_reads;
+
+    let low_complexity_reads =
+        count_low_complexity_reads(masked_bases, read_lengths, options.min_proportion);
+    // Weighted by base, the reads count for the bases of their analyzed part, the one masked
+    let (low_complexity_count, total_weight) = match options.weight {
+        Weight::ByRead => (low_complexity_reads, total_reads),
+        Weight::ByBase => (
+            count_low_complexity_bases(masked_bases, analyzed_lengths, options.min_proportion),
+            analyzed_lengths.iter().sum(),
+        ),
+    };
+
+    let mut proportions: HashMap<String, f64> = initialize_all_motifs(options);
+    let mut counts: HashMap<String, u64> =
+        proportions.keys().map(|motif| (motif.clone(), 0)).collect();
+
+    for (motif, &count) in motif_counts {
+        let motif = motif.name(options);
+        proportions.insert(motif.clone(), percentage(count as usize, total_weight));
+        counts.insert(motif, count);
+    }
+
+    // The categories keep their proportion of reads, and LowComplexity becomes the same
+    // statistic of the masked percentages
+    let mut low_complexity_proportion = percentage(low_complexity_count, total_weight);
+    if let Some(stat) = options.frequency_stat().filter(|_| total_reads > 0) {
+        if stat.needs_read_frequencies() {
+            for (motif, frequencies) in read_frequencies {
+                proportions.insert(
+                    motif.name(options),
+                    stat.compute(frequencies, total_reads) * 100.0,
+                );
+            }
+        } else {
+            for (motif, &frequency) in frequency_sums {
+                proportions.insert(motif.name(options), frequency / total_weight as f64 * 100.0);
+            }
+        }
+        let masked_fractions: Vec<f64> = masked_bases
+            .iter()
+            .zip(read_lengths)
+            .map(|(&masked, &length)| masked_fraction(masked, length))
+            .collect();
+        low_complexity_proportion = match options.weight {
+            Weight::ByRead => stat.compute(&masked_fractions, total_reads) * 100.0,
+            // Only the mean is weighted by base, giving the masked fraction of all the bases
+            Weight::ByBase => percentage(masked_bases.iter().sum(), total_weight),
+        };
+    }
+
+    // Add low-complexity result
+    proportions.insert(LOW_COMPLEXITY_MOTIF.to_string(), low_complexity_proportion);
+    counts.insert(
+        LOW_COMPLEXITY_MOTIF.to_string(),
+        low_complexity_count as u64,
+    );
+
+    let length_bins = match &options.length_bins {
+        Some(bins) => {
+            let read_bins: Vec<String> = read_lengths
+                .iter()
+                .map(|&length| bins.label(length))
+                .collect();
+            compute_grouped_proportions(
+                bins.labels(),
+                &read_bins,
+                binned_counts,
+                read_lengths,
+                masked_bases,
+                options,
+            )
+        }
+        None => Vec::new(),
+    };
+    // Tiles are sorted by label, which puts the unknown one last
+    let mut tiles: Vec<String> = read_tiles
+        .iter()
+        .cloned()
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    tiles.sort();
+    let tiles = compute_grouped_proportions(
+        tiles,
+        read_tiles,
+        tile_counts,
+        read_lengths,
+        masked_bases,
+        options,
+    );
+
+    MotifStats {
+        total_reads,
+        proportions: sort_proportions(proportions),
+        counts,
+        low_complexity_proportion,
+        length_bins,
+        tiles,
+        mate_proportions: mate_counts.as_ref().map(|mate_counts| {
+            compute_mate_proportions(mate_counts, read_lengths, masked_bases, options)
+        }),
+        read_lengths: LengthSummary::from_lengths(read_lengths.iter().copied()),
+        gc_content: gc_stats.as_ref().map(GcStats::overall_gc),
+        mean_quality: (*quality_reads > 0).then(|| quality_sum / *quality_reads as f64),
+        position_profile: compute_position_profile(
+            &options.position_motifs,
+            position_counts,
+            position_reads,
+        ),
+        enrichment: enrichment_stats
+            .as_ref()
+            .map(|enrichment_stats| enrichment_stats.log2_enrichment(options)),
+    }
+}
+
+/// A motif as it is counted, only named once the statistics are computed
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Motif {
+    /// Motif of `length` bases packed in 2 bits by base, in the order of the alphabet,
+    /// and 3 when the case is kept
+    Packed { length: usize, bases: u64 },
+    /// Motif too long to be packed
+    Named(String),
+    /// Category of reads, such as [`AMBIGUOUS_MOTIF`]
+    Category(&'static str),
+}
+
+impl Motif {
+    /// Counted motif of a name, packed when it can be
+    fn from_name(name: &str, options: &MotifOptions) -> Motif {
+        MotifPacking::new(name.len(), options)
+            .and_then(|packing| packing.pack(name.as_bytes()))
+            .map_or_else(
+                || Motif::Named(name.to_string()),
+                |bases| Motif::Packed {
+                    length: name.len(),
+                    bases,
+                },
+            )
+    }
+
+    /// Name of the motif, its bases as read with the given options
+    pub fn name(&self, options: &MotifOptions) -> String {
+        match self {
+            Motif::Packed { length, bases } => MotifPacking::new(*length, options)
+                .expect("packed motifs fit in 64 bits")
+                .unpack(*bases),
+            Motif::Named(name) => name.clone(),
+            Motif::Category(name) => name.to_string(),
+        }
+    }
+}
+
+/// Largest number of packed motifs of a length counted in a table, larger ones being hashed
+const MAX_MOTIF_TABLE_SIZE: usize = 1 << 16;
+
+/// Packing of the motifs into integers: 2 bits by base, in the order of the alphabet, and a third
+/// one set for lowercase bases when the case is kept. Packed motifs sort as their bases do.
+#[derive(Clone, Copy)]
+struct MotifPacking {
+    k: usize,
+    bits: usize,
+    alphabet: Alphabet,
+}
+
+impl MotifPacking {
+    /// Packing of the motifs of length `k`, if they fit in 64 bits
+    fn new(k: usize, options: &MotifOptions) -> Option<MotifPacking> {
+        let bits = if options.preserve_case { 3 } else { 2 };
+        (k * bits <= 64).then_some(MotifPacking {
+            k,
+            bits,
+            alphabet: options.alphabet,
+        })
+    }
+
+    /// Number of packed motifs, if it is small enough for a table
+    fn table_size(self) -> Option<usize> {
+        let size = 1usize.checked_shl((self.k * self.bits) as u32)?;
+        (size <= MAX_MOTIF_TABLE_SIZE).then_some(size)
+    }
+
+    fn mask(self) -> u64 {
+        u64::MAX >> (64 - self.k * self.bits)
+    }
+
+    /// Packed motif, None if one of its bases is out of the alphabet
+    fn pack(self, motif: &[u8]) -> Option<u64> {
+        motif.iter().try_fold(0, |packed, &base| {
+            Some((packed << self.bits) | self.pack_base(base)?)
+        })
+    }
+
+    /// Packed base, None for a base out of the alphabet
+    fn pack_base(self, base: u8) -> Option<u64> {
+        let index = self.alphabet.index(base)? as u64;
+        Some(if self.bits == 3 && base.is_ascii_lowercase() {
+            index | 0b100
+        } else {
+            index
+        })
+    }
+
+    /// Packed reverse complement of a packed motif, keeping the case of its bases
+    fn reverse_complement(self, mut motif: u64) -> u64 {
+        let base_mask = (1 << self.bits) - 1;
+        let mut reverse = 0;
+        for _ in 0..self.k {
+            // The complement of the base at index i of the alphabet is at index 3 - i
+            reverse = (reverse << self.bits) | ((motif & base_mask) ^ 0b11);
+            motif >>= self.bits;
+        }
+        reverse
+    }
+
+    fn unpack(self, motif: u64) -> String {
+        let bases = self.alphabet.bases();
+        let base_mask = (1 << self.bits) - 1;
+        (0..self.k)
+            .rev()
+            .map(|i| {
+                let base = (motif >> (i * self.bits)) & base_mask;
+                let letter = bases[(base & 0b11) as usize];
+                char::from(if base & 0b100 != 0 {
+                    letter.to_ascii_lowercase()
+                } else {
+                    letter
+                })
+            })
+            .collect()
+    }
+}
+
+/// Numbers of the packed motifs of a read, kept from one read to the next so that counting
+/// them allocates nothing once the buffers are sized
+#[derive(Default)]
+struct MotifCounter {
+    /// Number of each packed motif, when they fit in a table
+    table: Vec<u32>,
+    /// Packed motifs found in the table, to read and clear it
+    found: Vec<u64>,
+    /// Number of each packed motif, when they are too many for a table
+    hashed: HashMap<u64, u32>,
+    /// Motifs and numbers of the last read counted
+    counts: Vec<(u64, u32)>,
+}
+
+impl MotifCounter {
+    /// Counts the packed motifs of the windows of length `k` starting every `step` bases,
+    /// returning the number of windows holding a base out of the alphabet
+    fn count(
+        &mut self,
+        sequence: &[u8],
+        packing: MotifPacking,
+        step: usize,
+        canonical: bool,
+    ) -> usize {
+        let table_size = packing.table_size();
+        if let Some(size) = table_size {
+            if self.table.len() < size {
+                self.table.resize(size, 0);
+            }
+        }
+        let k = packing.k;
+        let mask = packing.mask();
+        let mut motif = 0;
+        // Number of bases of the alphabet in a row up to the current one
+        let mut valid_bases = 0;
+        let mut ambiguous_motifs = 0;
+        for (end, &base) in sequence.iter().enumerate() {
+            match packing.pack_base(base) {
+                Some(packed) => {
+                    motif = ((motif << packing.bits) | packed) & mask;
+                    valid_bases += 1;
+                }
+                None => valid_bases = 0,
+            }
+            if end + 1 < k || !(end + 1 - k).is_multiple_of(step) {
+                continue;
+            }
+            if valid_bases < k {
+                ambiguous_motifs += 1;
+                continue;
+            }
+            let counted = if canonical {
+                motif.min(packing.reverse_complement(motif))
+            } else {
+                motif
+            };
+            if table_size.is_some() {
+                let count = &mut self.table[counted as usize];
+                if *count == 0 {
+                    self.found.push(counted);
+                }
+                *count += 1;
+            } else {
+                *self.hashed.entry(counted).or_insert(0) += 1;
+            }
+        }
+
+        self.counts.clear();
+        for motif in self.found.drain(..) {
+            let count = &mut self.table[motif as usize];
+            self.counts.push((motif, *count));
+            *count = 0;
+        }
+        self.counts.extend(self.hashed.drain());
+        ambiguous_motifs
+    }
+}
+
+/// Counts the motifs of length `k` of a read, starting every `step` bases, and the number of
+/// windows holding a base out of the alphabet
+fn count_read_motifs(
+    sequence: &[u8],
+    k: usize,
+    step: usize,
+    options: &MotifOptions,
+    counter: &mut MotifCounter,
+) -> (Vec<(Motif, u64)>, usize) {
+    if let Some(packing) = MotifPacking::new(k, options) {
+        let ambiguous_motifs = counter.count(sequence, packing, step, options.canonical);
+        let motifs = counter
+            .counts
+            .iter()
+            .map(|&(bases, count)| (Motif::Packed { length: k, bases }, count as u64))
+            .collect();
+        return (motifs, ambiguous_motifs);
+    }
+
+    // Motifs too long to be packed are counted by name
+    let mut motif_frequencies: HashMap<String, u64> = HashMap::new();
+    let mut ambiguous_motifs = 0;
+    for motif in sequence.windows(k).step_by(step) {
+        if !motif
+            .iter()
+            .all(|&base| is_nucleotide(base, options.alphabet))
+        {
+            ambiguous_motifs += 1;
+            continue;
+        }
+        let motif = if options.canonical {
+            canonical_motif(motif, options.alphabet)
+        } else {
+            String::from_utf8_lossy(motif).into_owned()
+        };
+        *motif_frequencies.entry(motif).or_insert(0) += 1;
+    }
+    let motifs = motif_frequencies
+        .into_iter()
+        .map(|(motif, count)| (Motif::Named(motif), count))
+        .collect();
+    (motifs, ambiguous_motifs)
+}
+
+/// Motifs found above the minimum proportion in a read, its number of masked bases
+/// and its base composition
+struct ReadAnalysis {
+    motifs: Vec<Motif>,
+    masked: usize,
+    gc_bases: usize,
+    acgt_bases: usize,
+    /// Index of the profiled motif and position of each of their occurrences
+    motif_positions: Vec<(usize, usize)>,
+    /// Homopolymer runs, when computed
+    homopolymer_runs: Vec<(usize, usize)>,
+    /// Base and motif occurrences, when the enrichment is computed
+    occurrences: Option<ReadOccurrences>,
+    /// Shannon entropy of the bases, when a minimum entropy is set and the read has A/C/G/T bases
+    entropy: Option<f64>,
+    /// Number of each dinucleotide, when their composition is computed
+    dinucleotide_counts: Option<[u64; 16]>,
+    /// Frequency of each motif among the motifs of its length, when their statistic is reported
+    frequencies: Vec<(Motif, f64)>,
+    /// Highest frequency of a motif among the motifs of its length, unless the read has no
+    /// motif counted
+    max_proportion: Option<f64>,
+    /// Motifs inside and outside the masked regions, when counted separately
+    masked_composition: Option<MaskedComposition>,
+    /// Intervals masked by the built-in DUST, when kept
+    dust_intervals: Option<Vec<(usize, usize)>>,
+}
+
+/// Finds the motifs of the given lengths, or of the panel, exceeding the minimum proportion
+/// in a read, and masks its low-complexity regions with the built-in DUST if requested
+fn analyze_read(
+    sequence: &[u8],
+    options: &MotifOptions,
+    motif_lengths: &[usize],
+    panel: Option<&HashSet<Motif>>,
+    native_dust: bool,
+    counter: &mut MotifCounter,
+) -> ReadAnalysis {
+    let mut motifs = Vec::new();
+    let mut frequencies = Vec::new();
+    let mut max_proportion: Option<f64> = None;
+    let length = sequence.len();
+
+    // A read is counted once in the ambiguous category, whatever the motif length
+    let mut ambiguous_read = false;
+    // The reverse strand of double-stranded content is scanned like a second read
+    let reverse = options
+        .both_strands
+        .then(|| reverse_complement(sequence, options.alphabet));
+    let strands = if reverse.is_some() { 2 } else { 1 };
+
+    let mut occurrences = options.enrichment.then(|| {
+        let mut counts = base_counts(sequence, options.alphabet);
+        if reverse.is_some() {
+            // A, C, G and T, whose complements are in the reverse order
+            counts = [0, 1, 2, 3].map(|index| counts[index] + counts[3 - index]);
+        }
+        ReadOccurrences {
+            base_counts: counts,
+            motifs: Vec::new(),
+            windows: Vec::new(),
+        }
+    });
+
+    for &k in motif_lengths {
+        if length < k {
+            continue;
+        }
+        let min_proportion = options.kmer_min_proportion(k);
+
+        // Non-overlapping motifs tile the read, starting from its first base
+        let step = if options.non_overlapping { k } else { 1 };
+
+        let (mut motif_frequencies, mut ambiguous_motifs) =
+            count_read_motifs(sequence, k, step, options, counter);
+        if let Some(reverse) = &reverse {
+            let (reverse_frequencies, reverse_ambiguous) =
+                count_read_motifs(reverse, k, step, options, counter);
+            let mut counts: HashMap<Motif, u64> = motif_frequencies.into_iter().collect();
+            for (motif, count) in reverse_frequencies {
+                *counts.entry(motif).or_insert(0) += count;
+            }
+            motif_frequencies = counts.into_iter().collect();
+            ambiguous_motifs += reverse_ambiguous;
+        }
+
+        // Skipped ambiguous motifs are left out of the denominator
+        let mut total_motifs = strands * ((length - k) / step + 1);
+        if let Some(occurrences) = occurrences.as_mut() {
+            let windows = (total_motifs - ambiguous_motifs) as u64;
+            occurrences.windows.push((k, windows));
+        }
+        if options.ambiguous == AmbiguousMotifs::Skip {
+            total_motifs -= ambiguous_motifs;
+        } else if (ambiguous_motifs as f64) / (total_motifs as f64) > min_proportion {
+            ambiguous_read = true;
+        }
+        if total_motifs == 0 {
+            continue;
+        }
+
+        for (motif, count) in motif_frequencies {
+            if panel.is_some_and(|panel| !panel.contains(&motif)) {
+                continue;
+            }
+            let proportion = (count as f64) / (total_motifs as f64);
+            if max_proportion.is_none_or(|max_proportion| proportion > max_proportion) {
+                max_proportion = Some(proportion);
+            }
+            if let Some(occurrences) = occurrences.as_mut() {
+                occurrences.motifs.push((motif.clone(), count));
+            }
+            if options.frequency_stat().is_some() {
+                frequencies.push((motif.clone(), proportion));
+            }
+            if proportion > min_proportion {
+                motifs.push(motif);
+            }
+        }
+    }
+
+    if ambiguous_read {
+        motifs.push(Motif::Category(AMBIGUOUS_MOTIF));
+    }
+
+    let entropy = options.min_entropy.and_then(|min_entropy| {
+        let entropy = base_entropy(sequence, options.alphabet)?;
+        if entropy < min_entropy {
+            motifs.push(Motif::Category(LOW_ENTROPY_MOTIF));
+        }
+        Some(entropy)
+    });
+
+    let intervals = if native_dust {
+        dust::sdust(sequence, options.dust_threshold, options.dust_window)
+    } else {
+        Vec::new()
+    };
+    let masked = intervals
+        .iter()
+        .map(|&(start, end)| interval_length(start, end))
+        .sum();
+    let masked_composition = (native_dust && options.masked_composition).then(|| {
+        let mut composition = MaskedComposition::default();
+        composition.add_read(sequence, &intervals, options, motif_lengths, panel, counter);
+        composition
+    });
+
+    let (gc_bases, acgt_bases) = if options.gc_content {
+        let gc_bases = sequence
+            .iter()
+            .filter(|base| matches!(base, b'G' | b'C' | b'g' | b'c'))
+            .count();
+        let acgt_bases = sequence
+            .iter()
+            .filter(|&&base| is_nucleotide(base, options.alphabet))
+            .count();
+        (gc_bases, acgt_bases)
+    } else {
+        (0, 0)
+    };
+
+    // With --canonical, a profiled motif also matches its reverse complement
+    let mut motif_positions = Vec::new();
+    for (index, motif) in options.position_motifs.iter().enumerate() {
+        let motif = motif.as_bytes();
+        let reverse = options
+            .canonical
+            .then(|| reverse_complement(motif, options.alphabet));
+        for (position, window) in sequence.windows(motif.len()).enumerate() {
+            if window == motif || reverse.as_deref() == Some(window) {
+                motif_positions.push((index, position));
+            }
+        }
+    }
+
+    let dinucleotide_counts = options
+        .dinucleotide_composition
+        .then(|| dinucleotide_counts(sequence, options.alphabet));
+
+    let homopolymer_runs = if options.homopolymers {
+        homopolymer_runs(sequence, options.alphabet)
+    } else {
+        Vec::new()
+    };
+
+    ReadAnalysis {
+        motifs,
+        masked,
+        gc_bases,
+        acgt_bases,
+        motif_positions,
+        homopolymer_runs,
+        occurrences,
+        entropy,
+        dinucleotide_counts,
+        frequencies,
+        max_proportion,
+        masked_composition,
+        dust_intervals: (native_dust && options.dust_intervals).then_some(intervals),
+    }
+}
+
+/// Number of each base of the alphabet in a sequence, whatever their case
+fn base_counts(sequence: &[u8], alphabet: Alphabet) -> [u64; 4] {
+    let mut counts = [0; 4];
+    for &base in sequence {
+        if let Some(index) = alphabet.index(base) {
+            counts[index] += 1;
+        }
+    }
+    counts
+}
+
+/// Number of bases covered by a masked interval, 0-based and half-open as `sdust` and the
+/// built-in DUST report them
+fn interval_length(start: usize, end: usize) -> usize {
+    end - start
+}
+
+/// Number of bases covered by the masked intervals of a read, sorted and merged first so that
+/// the bases of overlapping intervals are only counted once
+fn masked_length(intervals: &mut [(usize, usize)]) -> usize {
+    intervals.sort_unstable();
+    let mut masked = 0;
+    let mut merged: Option<(usize, usize)> = None;
+    for &(start, end) in intervals.iter() {
+        merged = match merged {
+            Some((merged_start, merged_end)) if start <= merged_end => {
+                Some((merged_start, merged_end.max(end)))
+            }
+            _ => {
+                if let Some((merged_start, merged_end)) = merged {
+                    masked += interval_length(merged_start, merged_end);
+                }
+                Some((start, end))
+            }
+        };
+    }
+    if let Some((merged_start, merged_end)) = merged {
+        masked += interval_length(merged_start, merged_end);
+    }
+    masked
+}
+
+/// Parses a line of the SDUST output into the read index, start and end of a masked interval.
+/// Lines that are not made of three fields are left out.
+fn parse_dust_line(line: &str) -> Result<Option<(usize, usize, usize)>, AppError> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    if parts.len() != 3 {
+        return Ok(None);
+    }
+    let read_index: usize = parts[0]
+        .parse()
+        .map_err(|_| AppError::Sdust(format!("Unknown read in SDUST output: {}", line)))?;
+    let start: usize = parts[1].parse().map_err(|_| {
+        AppError::Sdust(format!("Invalid start position in SDUST output: {}", line))
+    })?;
+    let end: usize = parts[2]
+        .parse()
+        .map_err(|_| AppError::Sdust(format!("Invalid end position in SDUST output: {}", line)))?;
+    Ok(Some((read_index, start, end)))
+}
+
+/// Parses the SDUST output as it is produced, adding up the masked bases of each read, once
+/// its intervals are merged.
+/// Reads are named by their index in the FASTA given to SDUST, and the returned counts go up
+/// to the last read with a masked interval.
+pub fn read_dust_output<R: BufRead>(dust_output: R) -> Result<Vec<usize>, AppError> {
+    let mut intervals = read_dust_intervals(dust_output)?;
+    Ok(intervals
+        .iter_mut()
+        .map(|read_intervals| masked_length(read_intervals))
+        .collect())
+}
+
+/// Parses the masked intervals of each read from the SDUST output, as `(start, end)` pairs
+/// with the end excluded. The returned reads go up to the last one with a masked interval.
+pub fn parse_dust_intervals(dust_data: &str) -> Result<Vec<Vec<(usize, usize)>>, AppError> {
+    read_dust_intervals(dust_data.as_bytes())
+}
+
+/// Reads the masked intervals of each read from the SDUST output, as it is produced
+fn read_dust_intervals<R: BufRead>(dust_output: R) -> Result<Vec<Vec<(usize, usize)>>, AppError> {
+    let mut intervals: Vec<Vec<(usize, usize)>> = Vec::new();
+    for line in dust_output.lines() {
+        let line =
+            line.map_err(|e| AppError::Sdust(format!("Failed to read SDUST output: {}", e)))?;
+        if let Some((read_index, start, end)) = parse_dust_line(&line)? {
+            if intervals.len() <= read_index {
+                intervals.resize(read_index + 1, Vec::new());
+            }
+            intervals[read_index].push((start, end));
+        }
+    }
+    Ok(intervals)
+}
+
+/// Checks that the masked intervals parsed from the SDUST output lie within their reads, of the
+/// given lengths, and do not end before they start
+pub fn check_dust_intervals(
+    intervals: &[Vec<(usize, usize)>],
+    read_lengths: &[usize],
+) -> Result<(), AppError> {
+    for (read_index, read_intervals) in intervals.iter().enumerate() {
+        let length = read_lengths.get(read_index).copied().ok_or_else(|| {
+            AppError::Sdust(format!("Unknown read in SDUST output: {}", read_index))
+        })?;
+        for &(start, end) in read_intervals {
+            if start > end || end > length {
+                return Err(AppError::Sdust(format!(
+                    "Invalid interval in SDUST output: {}\t{}\t{} (read of {} bases)",
+                    read_index, start, end, length
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Parses the SDUST output to calculate the number of masked bases per read.
+/// Reads are named by their index in the temporary FASTA file.
+pub fn parse_dust_output(dust_data: &str, total_reads: usize) -> Result<Vec<usize>, AppError> {
+    let mut masked_bases = read_dust_output(dust_data.as_bytes())?;
+    if masked_bases.len() > total_reads {
+        return Err(AppError::Sdust(format!(
+            "Unknown read in SDUST output: {}",
+            masked_bases.len() - 1
+        )));
+    }
+    masked_bases.resize(total_reads, 0);
+    Ok(masked_bases)
+}
+
+/// Percentage of `count` over `total`, 0 when there is nothing to count
+fn percentage(count: usize, total: usize) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+    (count as f64 / total as f64) * 100.0
+}
+
+/// Fraction of the bases of a read that are masked, 0 for an empty read
+fn masked_fraction(masked: usize, length: usize) -> f64 {
+    if length == 0 {
+        return 0.0;
+    }
+    masked as f64 / length as f64
+}
+
+/// Sums the analyzed lengths of the reads whose masked proportion of their analyzed part
+/// exceeds the minimum proportion
+fn count_low_complexity_bases(
+    masked_bases: &[usize],
+    analyzed_lengths: &[usize],
+    min_proportion: f64,
+) -> usize {
+    masked_bases
+        .iter()
+        .zip(analyzed_lengths)
+        .filter(|&(&masked, &length)| masked_fraction(masked, length) > min_proportion)
+        .map(|(_, &length)| length)
+        .sum()
+}
+
+/// Counts the reads whose masked proportion exceeds the minimum proportion
+fn count_low_complexity_reads(
+    masked_bases: &[usize],
+    read_lengths: &[usize],
+    min_proportion: f64,
+) -> usize {
+    let mut low_complexity_reads = 0;
+
+    for (&masked, &total_length) in masked_bases.iter().zip(read_lengths) {
+        if masked_fraction(masked, total_length) > min_proportion {
+            low_complexity_reads += 1;
+        }
+    }
+
+    low_complexity_reads
+}
+
+/// Sorts proportions by descending value, then by motif
+fn sort_proportions(proportions: HashMap<String, f64>) -> Vec<(String, f64)> {
+    let mut sorted_proportions: Vec<(String, f64)> = proportions.into_iter().collect();
+    // Ties are ordered by motif, for the output not to depend on the hashing of the motifs
+    sorted_proportions.sort_by(|a, b| {
+        b.1.partial_cmp(&a.1)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| a.0.cmp(&b.0))
+    });
+    sorted_proportions
+}
+
+/// Computes the sorted motif and low-complexity proportions, and the counts, of each group of
+/// reads (read-length bin or tile), given the group of each read. Empty groups are left out
+fn compute_grouped_proportions(
+    groups: Vec<String>,
+    read_groups: &[String],
+    grouped_counts: &HashMap<(String, Motif), u64>,
+    read_lengths: &[usize],
+    masked_bases: &[usize],
+    options: &MotifOptions,
+) -> Vec<LengthBinStats> {
+    let mut bin_reads: HashMap<&str, usize> = HashMap::new();
+    for group in read_groups {
+        *bin_reads.entry(group).or_insert(0) += 1;
+    }
+
+    let mut low_complexity_reads: HashMap<&str, usize> = HashMap::new();
+    for ((&masked, &length), group) in masked_bases.iter().zip(read_lengths).zip(read_groups) {
+        if masked_fraction(masked, length) > options.min_proportion {
+            *low_complexity_reads.entry(group).or_insert(0) += 1;
+        }
+    }
+
+    let mut length_bins = Vec::new();
+    for bin in groups {
+        let total_reads = bin_reads.get(bin.as_str()).copied().unwrap_or(0);
+        if total_reads == 0 {
+            continue;
+        }
+        let proportion = |count: f64| (count / total_reads as f64) * 100.0;
+
+        let mut proportions = initialize_all_motifs(options);
+        let mut counts: HashMap<String, u64> =
+            proportions.keys().map(|motif| (motif.clone(), 0)).collect();
+        for ((count_bin, motif), &count) in grouped_counts {
+            if *count_bin == bin {
+                let motif = motif.name(options);
+                proportions.insert(motif.clone(), proportion(count as f64));
+                counts.insert(motif, count);
+            }
+        }
+        let low_complexity = low_complexity_reads.get(bin.as_str()).copied().unwrap_or(0);
+        proportions.insert(
+            LOW_COMPLEXITY_MOTIF.to_string(),
+            proportion(low_complexity as f64),
+        );
+        counts.insert(LOW_COMPLEXITY_MOTIF.to_string(), low_complexity as u64);
+
+        length_bins.push(LengthBinStats {
+            label: bin,
+            total_reads,
+            proportions: sort_proportions(proportions),
+            counts,
+        });
+    }
+    length_bins
+}
+
+/// Computes the fraction of the reads covering each profiled motif at each position in which
+/// it occurs, by position
+fn compute_position_profile(
+    motifs: &[String],
+    position_counts: &[Vec<u64>],
+    position_reads: &[u64],
+) -> Vec<(usize, String, f64)> {
+    let mut profile = Vec::new();
+    for position in 0..position_reads.len() {
+        for (motif, counts) in motifs.iter().zip(position_counts) {
+            // Reads covering the last base of the motif
+            let Some(&reads) = position_reads.get(position + motif.len() - 1) else {
+                continue;
+            };
+            let count = counts.get(position).copied().unwrap_or(0);
+            profile.push((position, motif.clone(), count as f64 / reads as f64));
+        }
+    }
+    profile
+}
+
+/// Computes the motif and low-complexity proportions of each mate of paired-end reads
+fn compute_mate_proportions(
+    mate_counts: &MateCounts,
+    read_lengths: &[usize],
+    masked_bases: &[usize],
+    options: &MotifOptions,
+) -> [HashMap<String, f64>; 2] {
+    let mut mate_reads = [0; 2];
+    let mut low_complexity_reads = [0; 2];
+    for ((&mate, &masked), &length) in mate_counts.mates.iter().zip(masked_bases).zip(read_lengths)
+    {
+        mate_reads[mate as usize] += 1;
+        if masked_fraction(masked, length) > options.min_proportion {
+            low_complexity_reads[mate as usize] += 1;
+        }
+    }
+
+    [0, 1].map(|mate| {
+        let mut proportions = initialize_all_motifs(options);
+        if mate_reads[mate] == 0 {
+            return proportions;
+        }
+        let proportion = |count: f64| (count / mate_reads[mate] as f64) * 100.0;
+        for (motif, &count) in &mate_counts.motif_counts[mate] {
+            proportions.insert(motif.name(options), proportion(count as f64));
+        }
+        proportions.insert(
+            LOW_COMPLEXITY_MOTIF.to_string(),
+            proportion(low_complexity_reads[mate] as f64),
+        );
+        proportions
+    })
+}
+
+/// Layout of the results CSV file
+pub struct CsvFormat {
+    /// Separator of the fields
+    pub delimiter: char,
+    /// Write the names of the columns on the first line
+    pub header: bool,
+    /// Unit of the proportions
+    pub units: Units,
+    /// Name of the sample, written in a first `Sample` column if given
+    pub sample: Option<String>,
+}
+
+impl Default for CsvFormat {
+    /// Comma-separated, with a header, in percent, without sample name
+    fn default() -> CsvFormat {
+        CsvFormat {
+            delimiter: ',',
+            header: true,
+            units: Units::Percent,
+            sample: None,
+        }
+    }
+}
+
+/// Writes the results as CSV, to a file or to stdout, with the number of reads behind each
+/// proportion.
+/// When read-length bins are set, a `LengthBin` column is added
+/// where the proportions over all reads are labelled `all`.
+/// When proportions by mate are given, they are added as `R1` and `R2` columns,
+/// left empty on the rows of the read-length bins, and so is the `Log2Enrichment` column
+/// added when the enrichment is computed.
+/// When the name of the sample is given, it fills a first `Sample` column in.
+pub fn save_to_csv(
+    writer: &mut dyn Write,
+    stats: &MotifStats,
+    mate_data: Option<&[HashMap<String, f64>; 2]>,
+    format: &CsvFormat,
+) -> io::Result<()> {
+    let binned = !stats.length_bins.is_empty();
+    let count =
+        |counts: &HashMap<String, u64>, motif: &str| counts.get(motif).copied().unwrap_or(0);
+
+    let delimiter = format.delimiter.to_string();
+    let mut write_row = |mut fields: Vec<String>, sample: &str| {
+        if format.sample.is_some() {
+            fields.insert(0, sample.to_string());
+        }
+        writeln!(writer, "{}", fields.join(&delimiter))
+    };
+    let sample = format.sample.as_deref().unwrap_or_default();
+
+    if format.header {
+        let mut header = vec!["Motif", format.units.column(), "Count", "TotalReads"];
+        if binned {
+            header.insert(0, "LengthBin");
+        }
+        if mate_data.is_some() {
+            header.extend(["R1", "R2"]);
+        }
+        if stats.enrichment.is_some() {
+            header.push("Log2Enrichment");
+        }
+        write_row(
+            header.iter().map(|name| name.to_string()).collect(),
+            "Sample",
+        )?;
+    }
+
+    for (motif, proportion) in &stats.proportions {
+        let mut fields = vec![
+            motif.clone(),
+            format.units.format(*proportion),
+            count(&stats.counts, motif).to_string(),
+            stats.total_reads.to_string(),
+        ];
+        if binned {
+            fields.insert(0, "all".to_string());
+        }
+        if let Some([r1, r2]) = mate_data {
+            for mate in [r1, r2] {
+                fields.push(format.units.format(mate.get(motif).copied().unwrap_or(0.0)));
+            }
+        }
+        if let Some(enrichment) = &stats.enrichment {
+            fields.push(
+                enrichment
+                    .get(motif)
+                    .map_or(String::new(), |log2_ratio| format!("{:.4}", log2_ratio)),
+            );
+        }
+        write_row(fields, sample)?;
+    }
+
+    for bin in &stats.length_bins {
+        for (motif, proportion) in &bin.proportions {
+            let mut fields = vec![
+                bin.label.clone(),
+                motif.clone(),
+                format.units.format(*proportion),
+                count(&bin.counts, motif).to_string(),
+                bin.total_reads.to_string(),
+            ];
+            if mate_data.is_some() {
+                fields.extend([String::new(), String::new()]);
+            }
+            if stats.enrichment.is_some() {
+                fields.push(String::new());
+            }
+            write_row(fields, sample)?;
+        }
+    }
+    Ok(())
+}
+
+/// Saves the distribution of the per-read GC content to a CSV file
+pub fn save_gc_histogram(output_file: &Path, gc_stats: &GcStats) -> io::Result<()> {
+    let mut file = AtomicFile::create(output_file)?;
+    writeln!(file, "GC,Reads")?;
+    for (percent, reads) in gc_stats.histogram.iter().enumerate() {
+        writeln!(file, "{},{}", percent, reads)?;
+    }
+    file.commit()
+}
+
+/// Saves the position profile of the profiled motifs to a CSV file
+pub fn save_position_profile(
+    output_file: &Path,
+    profile: &[(usize, String, f64)],
+) -> io::Result<()> {
+    let mut file = AtomicFile::create(output_file)?;
+    writeln!(file, "Position,Motif,Fraction")?;
+    for (position, motif, fraction) in profile {
+        writeln!(file, "{},{},{:.4}", position, motif, fraction)?;
+    }
+    file.commit()
+}
+
+/// Reads a motif panel from a file, one motif of A/C/G/T bases per line.
+/// Empty lines and lines starting with '#' are ignored, and motifs are uppercased unless
+/// `preserve_case` is set. Motifs may only hold bases of the alphabet.
+pub fn read_motifs_file(
+    path: &Path,
+    preserve_case: bool,
+    alphabet: Alphabet,
+) -> Result<Vec<String>, AppError> {
+    let error = |e: io::Error| {
+        AppError::Input(format!(
+            "Failed to read the motifs file {}: {}",
+            path.display(),
+            e
+        ))
+    };
+    let file = File::open(path).map_err(error)?;
+
+    let mut motifs = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(error)?;
+        let motif = line.trim();
+        if motif.is_empty() || motif.starts_with('#') {
+            continue;
+        }
+        if !motif.bytes().all(|base| is_nucleotide(base, alphabet)) {
+            let bases: Vec<String> = alphabet
+                .bases()
+                .iter()
+                .map(|&base| (base as char).to_string())
+                .collect();
+            return Err(AppError::Input(format!(
+                "Invalid motif '{}' in {}, only {} bases are expected",
+                motif,
+                path.display(),
+                bases.join("/")
+            )));
+        }
+        let motif = if preserve_case {
+            motif.to_string()
+        } else {
+            motif.to_ascii_uppercase()
+        };
+        if !motifs.contains(&motif) {
+            motifs.push(motif);
+        }
+    }
+
+    if motifs.is_empty() {
+        return Err(AppError::Input(format!(
+            "No motif found in {}",
+            path.display()
+        )));
+    }
+    Ok(motifs)
+}
+
+/// Saves the number and fraction of each dinucleotide among all the dinucleotides of the reads
+/// to a CSV file
+pub fn save_dinucleotide_composition(
+    output_file: &Path,
+    dinucleotide_stats: &DinucleotideStats,
+) -> io::Result<()> {
+    let mut file = AtomicFile::create(output_file)?;
+    writeln!(file, "Dinucleotide,Count,Fraction")?;
+    for (dinucleotide, count, fraction) in dinucleotide_stats.composition() {
+        writeln!(file, "{},{},{:.6}", dinucleotide, count, fraction)?;
+    }
+    file.commit()
+}
+
+/// Saves the motif and low-complexity proportions of each tile, with the number of reads behind
+/// them, to a CSV file
+pub fn save_tile_stats(
+    output_file: &Path,
+    tiles: &[LengthBinStats],
+    units: Units,
+) -> io::Result<()> {
+    let mut file = AtomicFile::create(output_file)?;
+    writeln!(file, "Tile,Motif,{},Count,TotalReads", units.column())?;
+    for tile in tiles {
+        for (motif, proportion) in &tile.proportions {
+            writeln!(
+                file,
+                "{},{},{},{},{}",
+                tile.label,
+                motif,
+                units.format(*proportion),
+                tile.counts.get(motif).copied().unwrap_or(0),
+                tile.total_reads
+            )?;
+        }
+    }
+    file.commit()
+}
+
+/// Saves the number and fraction of each base of the reads to a CSV file
+pub fn save_base_composition(
+    output_file: &Path,
+    base_composition: &BaseComposition,
+) -> io::Result<()> {
+    let mut file = AtomicFile::create(output_file)?;
+    writeln!(file, "Base,Count,Fraction")?;
+    for (base, count, fraction) in base_composition.composition() {
+        writeln!(file, "{},{},{:.6}", base, count, fraction)?;
+    }
+    file.commit()
+}
+
+/// Pearson's chi-square statistic, without continuity correction, of the 2x2 table of the reads
+/// with and without a motif in two samples, and its p-value (1 degree of freedom). `None` when
+/// the motif is in all the reads of both samples or in none of them
+fn chi_square_test(
+    count: u64,
+    total: u64,
+    other_count: u64,
+    other_total: u64,
+) -> Option<(f64, f64)> {
+    let (count, total, other_count, other_total) = (
+        count as f64,
+        total as f64,
+        other_count as f64,
+        other_total as f64,
+    );
+    let reads = total + other_total;
+    let with_motif = count + other_count;
+    let margins = total * other_total * with_motif * (reads - with_motif);
+    if margins == 0.0 {
+        return None;
+    }
+    let determinant = count * (other_total - other_count) - (total - count) * other_count;
+    let chi_square = reads * determinant * determinant / margins;
+    Some((chi_square, erfc((chi_square / 2.0).sqrt())))
+}
+
+/// Complementary error function of a non-negative value, to a relative error below 1.2e-7
+/// (Chebyshev fit of Numerical Recipes)
+fn erfc(x: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.5 * x);
+    let coefficients = [
+        -1.26551223,
+        1.00002368,
+        0.37409196,
+        0.09678418,
+        -0.18628806,
+        0.27886807,
+        -1.13520398,
+        1.48851587,
+        -0.82215223,
+        0.17087277,
+    ];
+    let polynomial = coefficients
+        .iter()
+        .rev()
+        .fold(0.0, |sum, coefficient| sum * t + coefficient);
+    t * (-x * x + polynomial).exp()
+}
+
+/// Saves the proportions of the motifs in two samples side by side, with their difference and
+/// the chi-square test of the numbers of reads with the motif, to a CSV file, sorted by
+/// descending absolute difference, then by motif
+pub fn save_comparison(
+    output_file: &Path,
+    stats: &MotifStats,
+    other_stats: &MotifStats,
+    units: Units,
+) -> io::Result<()> {
+    let proportions: HashMap<&str, f64> = stats
+        .proportions
+        .iter()
+        .map(|(motif, proportion)| (motif.as_str(), *proportion))
+        .collect();
+    let other_proportions: HashMap<&str, f64> = other_stats
+        .proportions
+        .iter()
+        .map(|(motif, proportion)| (motif.as_str(), *proportion))
+        .collect();
+    let mut motifs: Vec<&str> = proportions
+        .keys()
+        .chain(
+            other_proportions
+                .keys()
+                .filter(|motif| !proportions.contains_key(*motif)),
+        )
+        .copied()
+        .collect();
+    let difference = |motif: &str| {
+        proportions.get(motif).unwrap_or(&0.0) - other_proportions.get(motif).unwrap_or(&0.0)
+    };
+    motifs.sort_by(|a, b| {
+        difference(b)
+            .abs()
+            .partial_cmp(&difference(a).abs())
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| a.cmp(b))
+    });
+
+    let mut file = AtomicFile::create(output_file)?;
+    writeln!(
+        file,
+        "Motif,{column}1,Count1,TotalReads1,{column}2,Count2,TotalReads2,Difference,ChiSquare,PValue",
+        column = units.column()
+    )?;
+    for motif in motifs {
+        let count = stats.counts.get(motif).copied().unwrap_or(0);
+        let other_count = other_stats.counts.get(motif).copied().unwrap_or(0);
+        let test = chi_square_test(
+            count,
+            stats.total_reads as u64,
+            other_count,
+            other_stats.total_reads as u64,
+        );
+        writeln!(
+            file,
+            "{},{},{},{},{},{},{},{},{},{}",
+            motif,
+            units.format(proportions.get(motif).copied().unwrap_or(0.0)),
+            count,
+            stats.total_reads,
+            units.format(other_proportions.get(motif).copied().unwrap_or(0.0)),
+            other_count,
+            other_stats.total_reads,
+            units.format(difference(motif)),
+            test.map_or(String::new(), |(chi_square, _)| format!(
+                "{:.4}",
+                chi_square
+            )),
+            test.map_or(String::new(), |(_, p_value)| format!("{:.4e}", p_value))
+        )?;
+    }
+    file.commit()
+}
+
+/// Saves the occurrences of each motif in the masked regions and in the rest of the reads, with
+/// their fraction of the windows of the motif length in each, to a CSV file
+pub fn save_masked_composition(
+    output_file: &Path,
+    masked_composition: &MaskedComposition,
+    options: &MotifOptions,
+) -> io::Result<()> {
+    let mut file = AtomicFile::create(output_file)?;
+    writeln!(
+        file,
+        "Motif,MaskedCount,MaskedFraction,UnmaskedCount,UnmaskedFraction"
+    )?;
+    for (motif, [masked, unmasked], [masked_fraction, unmasked_fraction]) in
+        masked_composition.composition(options)
+    {
+        writeln!(
+            file,
+            "{},{},{:.6},{},{:.6}",
+            motif, masked, masked_fraction, unmasked, unmasked_fraction
+        )?;
+    }
+    file.commit()
+}
+
+/// Saves the number of reads by percentage of their most frequent motif to a CSV file
+pub fn save_max_motif_histogram(
+    output_file: &Path,
+    max_motif_stats: &MaxMotifStats,
+) -> io::Result<()> {
+    let mut file = AtomicFile::create(output_file)?;
+    writeln!(file, "MaxMotifProportion,Reads")?;
+    for (percent, reads) in max_motif_stats.histogram.iter().enumerate() {
+        writeln!(file, "{},{}", percent, reads)?;
+    }
+    file.commit()
+}
+
+/// Saves the number of homopolymer runs of each length and base to a CSV file
+pub fn save_homopolymer_stats(
+    output_file: &Path,
+    homopolymer_stats: &HomopolymerStats,
+) -> io::Result<()> {
+    let mut file = AtomicFile::create(output_file)?;
+    let [a, c, g, t] = homopolymer_stats.alphabet.bases().map(char::from);
+    writeln!(file, "RunLength,{},{},{},{}", a, c, g, t)?;
+    for (length, counts) in homopolymer_stats.run_counts.iter().enumerate().skip(1) {
+        writeln!(
+            file,
+            "{},{},{},{},{}",
+            length, counts[0], counts[1], counts[2], counts[3]
+        )?;
+    }
+    file.commit()
+}
+
+/// Saves the base and length of the longest homopolymer run of each read, named by its id
+/// (or by its index when the names were not kept), to a CSV file, gzipped if its name ends
+/// with `.gz`.
+/// Reads without A/C/G/T bases have an empty base and a length of 0.
+pub fn save_longest_homopolymers(
+    output_file: &Path,
+    homopolymer_stats: &HomopolymerStats,
+    read_names: &[String],
+) -> io::Result<()> {
+    let mut file = OutputFile::create(output_file)?;
+    writeln!(file, "Read,Base,Length")?;
+    for (index, longest) in homopolymer_stats.longest_runs.iter().enumerate() {
+        let read = read_label(read_names, index);
+        match longest {
+            Some((base, length)) => writeln!(file, "{},{},{}", read, *base as char, length)?,
+            None => writeln!(file, "{},,0", read)?,
+        }
+    }
+    file.finish()
+}
+
+/// Saves the distribution of the per-read masked fraction, in percent of the read length,
+/// to a CSV file
+pub fn save_low_complexity_distribution(
+    output_file: &Path,
+    masked_bases: &[usize],
+    read_lengths: &[usize],
+) -> io::Result<()> {
+    let mut histogram = [0u64; 101];
+    for (&masked, &length) in masked_bases.iter().zip(read_lengths) {
+        let percent = (masked_fraction(masked, length) * 100.0).round().min(100.0) as usize;
+        histogram[percent] += 1;
+    }
+
+    let mut file = AtomicFile::create(output_file)?;
+    writeln!(file, "MaskedPercent,Reads")?;
+    for (percent, reads) in histogram.iter().enumerate() {
+        writeln!(file, "{},{}", percent, reads)?;
+    }
+    file.commit()
+}
+
+/// Saves the masked bases, length and masked fraction of each read, named by its id
+/// (or by its index when the names were not kept), to a TSV file (gzipped if its name ends
+/// with `.gz`)
+pub fn save_per_read_dust(
+    output_file: &Path,
+    masked_bases: &[usize],
+    read_lengths: &[usize],
+    read_names: &[String],
+) -> io::Result<()> {
+    let mut file = OutputFile::create(output_file)?;
+    writeln!(file, "Read\tMaskedBases\tLength\tMaskedFraction")?;
+    for (read, (&masked, &length)) in masked_bases.iter().zip(read_lengths).enumerate() {
+        writeln!(
+            file,
+            "{}\t{}\t{}\t{:.4}",
+            read_label(read_names, read),
+            masked,
+            length,
+            masked_fraction(masked, length)
+        )?;
+    }
+    file.finish()
+}
+
+/// Id of the read at `index`, or its index when the names were not kept
+fn read_label(read_names: &[String], index: usize) -> String {
+    read_names
+        .get(index)
+        .map(|name| read_id(name).to_string())
+        .unwrap_or_else(|| index.to_string())
+}
+
+/// Saves the masked intervals of each read, named by its id (or by its index when the names
+/// were not kept), to a BED file (gzipped if its name ends with `.gz`)
+pub fn save_dust_bed(
+    output_file: &Path,
+    intervals: &[Vec<(usize, usize)>],
+    read_names: &[String],
+) -> io::Result<()> {
+    let mut file = OutputFile::create(output_file)?;
+    for (read_index, read_intervals) in intervals.iter().enumerate() {
+        let read = read_label(read_names, read_index);
+        for &(start, end) in read_intervals {
+            writeln!(file, "{}\t{}\t{}", read, start, end)?;
+        }
+    }
+    file.finish()
+}
+
+/// Saves the reads of the FASTA written for SDUST with their masked intervals lowercased,
+/// named as in the input (or by their index when the names were not kept), to a FASTA file
+/// (gzipped if its name ends with `.gz`)
+pub fn save_masked_fasta<R: BufRead>(
+    output_file: &Path,
+    fasta: R,
+    intervals: &[Vec<(usize, usize)>],
+    read_names: &[String],
+) -> io::Result<()> {
+    let mut file = OutputFile::create(output_file)?;
+    // Reads are named by their index, one sequence line each
+    let mut read_index = 0;
+    for line in fasta.lines() {
+        let line = line?;
+        if line.starts_with('>') {
+            match read_names.get(read_index) {
+                Some(name) => writeln!(file, ">{}", name)?,
+                None => writeln!(file, "{}", line)?,
+            }
+            continue;
+        }
+        let mut sequence = line.into_bytes();
+        for &(start, end) in intervals.get(read_index).into_iter().flatten() {
+            let end = end.min(sequence.len());
+            if let Some(masked) = sequence.get_mut(start..end) {
+                masked.make_ascii_lowercase();
+            }
+        }
+        file.write_all(&sequence)?;
+        writeln!(file)?;
+        read_index += 1;
+    }
+    file.finish()
+}
+
+/// Summary statistics of the read-length distribution
+#[derive(Debug, Clone, Serialize)]
+pub struct LengthSummary {
+    pub reads: usize,
+    /// Number of bases of the reads, their sequenced yield
+    pub total_bases: u64,
+    pub min: usize,
+    pub max: usize,
+    pub mean: f64,
+    pub median: f64,
+    pub n50: usize,
+}
+
+impl LengthSummary {
+    pub fn from_lengths(lengths: impl Iterator<Item = usize>) -> LengthSummary {
+        let mut lengths: Vec<usize> = lengths.collect();
+        lengths.sort_unstable();
+
+        let reads = lengths.len();
+        let total: usize = lengths.iter().sum();
+        let median = match reads {
+            0 => 0.0,
+            _ if reads % 2 == 1 => lengths[reads / 2] as f64,
+            _ => (lengths[reads / 2 - 1] + lengths[reads / 2]) as f64 / 2.0,
+        };
+
+        // Length of the shortest read among the longest ones covering half of the bases
+        let mut cumulated = 0;
+        let mut n50 = 0;
+        for &length in lengths.iter().rev() {
+            cumulated += length;
+            if cumulated * 2 >= total {
+                n50 = length;
+                break;
+            }
+        }
+
+        LengthSummary {
+            reads,
+            total_bases: total as u64,
+            min: lengths.first().copied().unwrap_or(0),
+            max: lengths.last().copied().unwrap_or(0),
+            mean: if reads > 0 {
+                total as f64 / reads as f64
+            } else {
+                0.0
+            },
+            median,
+            n50,
+        }
+    }
+}
+
+/// Saves the summary statistics of the read lengths to a CSV file
+pub fn save_length_summary(output_file: &Path, summary: &LengthSummary) -> io::Result<()> {
+    let mut file = AtomicFile::create(output_file)?;
+    writeln!(file, "Statistic,Value")?;
+    writeln!(file, "Reads,{}", summary.reads)?;
+    writeln!(file, "Bases,{}", summary.total_bases)?;
+    writeln!(file, "Min,{}", summary.min)?;
+    writeln!(file, "Max,{}", summary.max)?;
+    writeln!(file, "Mean,{:.2}", summary.mean)?;
+    writeln!(file, "Median,{:.1}", summary.median)?;
+    writeln!(file, "N50,{}", summary.n50)?;
+    file.commit()
+}
+
+/// Saves the histogram of the read lengths to a CSV file
+pub fn save_length_histogram(
+    output_file: &Path,
+    bins: &LengthBins,
+    read_lengths: &[usize],
+) -> io::Result<()> {
+    let mut bin_reads: HashMap<String, usize> = HashMap::new();
+    for &length in read_lengths {
+        *bin_reads.entry(bins.label(length)).or_insert(0) += 1;
+    }
+
+    let mut file = AtomicFile::create(output_file)?;
+    writeln!(file, "Length,Reads")?;
+    for bin in bins.labels() {
+        writeln!(
+            file,
+            "{},{}",
+            bin,
+            bin_reads.get(&bin).copied().unwrap_or(0)
+        )?;
+    }
+    file.commit()
+}
+
+/// Saves the motifs above the threshold to a tab-separated file
+pub fn save_flagged_motifs(output_file: &Path, flagged: &[(String, f64)]) -> io::Result<()> {
+    let mut file = AtomicFile::create(output_file)?;
+    writeln!(file, "Motif\tProportion")?;
+    for (motif, proportion) in flagged {
+        writeln!(file, "{}\t{:.4}", motif, proportion)?;
+    }
+    file.commit()
+}
+
+/// Run-level information saved alongside the motif proportions in the JSON output
+#[derive(Serialize)]
+pub struct JsonMetadata {
+    pub sample: String,
+    pub total_reads: usize,
+    pub max_reads: usize,
+    pub ratio: f64,
+    /// Unit of the proportions, `ratio` and `gc_content` staying percentages
+    pub units: Units,
+    /// Weight of each read in the proportions
+    pub weight: Weight,
+    pub low_complexity_proportion: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gc_content: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mean_quality: Option<f64>,
+    pub read_lengths: LengthSummary,
+    /// Minimum length of the analyzed reads, once trimmed
+    pub min_read_length: usize,
+    /// Number of reads left out for being too short
+    pub short_reads: usize,
+    /// Motifs above the `--ratio` threshold, by descending proportion
+    pub flagged_motifs: Vec<String>,
+    pub low_complexity_flagged: bool,
+    /// Seed of the random sampling of the reads, if they were sampled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+}
+
+/// A single motif entry of the JSON output
+#[derive(Serialize)]
+struct JsonMotif<'a> {
+    motif: &'a str,
+    proportion: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    r1: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    r2: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    log2_enrichment: Option<f64>,
+}
+
+/// Saves the distribution of the per-read base entropy to a CSV file, by tenth of a bit
+pub fn save_entropy_histogram(output_file: &Path, entropy_stats: &EntropyStats) -> io::Result<()> {
+    let mut file = AtomicFile::create(output_file)?;
+    writeln!(file, "Entropy,Reads")?;
+    for (bin, reads) in entropy_stats.histogram.iter().enumerate() {
+        writeln!(file, "{:.1},{}", bin as f64 / ENTROPY_BINS_PER_BIT, reads)?;
+    }
+    file.commit()
+}
+
+/// Number of motifs listed in the MultiQC table
+pub const MULTIQC_TOP_MOTIFS: usize = 3;
+
+/// Saves the key metrics of a sample as one row of a MultiQC custom-content table, the
+/// comment block on top telling MultiQC how to show it
+pub fn save_multiqc(output_file: &Path, sample: &str, stats: &MotifStats) -> io::Result<()> {
+    let mut file = AtomicFile::create(output_file)?;
+    writeln!(file, "# id: 'freq_motif'")?;
+    writeln!(file, "# section_name: 'Motif frequencies'")?;
+    writeln!(
+        file,
+        "# description: 'Reads dominated by a motif and low-complexity reads, from freq-motif-fastq'"
+    )?;
+    writeln!(file, "# plot_type: 'table'")?;
+    writeln!(file, "# pconfig:")?;
+    writeln!(file, "#     id: 'freq_motif_table'")?;
+    writeln!(file, "#     namespace: 'freq-motif'")?;
+    writeln!(file, "Sample\tTotalReads\tGC\tLowComplexity\tTopMotifs")?;
+
+    let top_motifs: Vec<String> = stats
+        .proportions
+        .iter()
+        .filter(|(motif, _)| motif != LOW_COMPLEXITY_MOTIF)
+        .take(MULTIQC_TOP_MOTIFS)
+        .map(|(motif, proportion)| format!("{} ({:.2}%)", motif, proportion))
+        .collect();
+    writeln!(
+        file,
+        "{}\t{}\t{}\t{:.2}\t{}",
+        sample,
+        stats.total_reads,
+        stats
+            .gc_content
+            .map_or(String::new(), |gc_content| format!("{:.2}", gc_content)),
+        stats.low_complexity_proportion,
+        top_motifs.join(", ")
+    )?;
+    file.commit()
+}
+
+/// Saves the results to a JSON file, with the log2 enrichment of the motifs when given
+pub fn save_to_json(
+    output_file: &Path,
+    metadata: &JsonMetadata,
+    data: &[(String, f64)],
+    mate_data: Option<&[HashMap<String, f64>; 2]>,
+    enrichment: Option<&HashMap<String, f64>>,
+) -> io::Result<()> {
+    let units = metadata.units;
+    #[derive(Serialize)]
+    struct JsonReport<'a> {
+        #[serde(flatten)]
+        metadata: &'a JsonMetadata,
+        motifs: Vec<JsonMotif<'a>>,
+    }
+
+    let report = JsonReport {
+        metadata,
+        motifs: data
+            .iter()
+            .map(|(motif, proportion)| JsonMotif {
+                motif,
+                proportion: units.convert(*proportion),
+                r1: mate_data.map(|[r1, _]| units.convert(r1.get(motif).copied().unwrap_or(0.0))),
+                r2: mate_data.map(|[_, r2]| units.convert(r2.get(motif).copied().unwrap_or(0.0))),
+                log2_enrichment: enrichment.and_then(|enrichment| enrichment.get(motif).copied()),
+            })
+            .collect(),
+    };
+
+    let mut file = AtomicFile::create(output_file)?;
+    serde_json::to_writer_pretty(&mut file, &report)?;
+    file.commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    /// Three reads: a dinucleotide repeat, a homopolymer and a read without any motif above 15%
+    const FASTQ: &str = "@repeat\nATATATATATAT\n+\nIIIIIIIIIIII\n\
+                         @homopolymer\nAAAAAAAAAAAA\n+\nIIIIIIIIIIII\n\
+                         @mixed\nACGTTGCA\n+\nIIIIIIII\n";
+
+    /// Dinucleotides only, the test reads being shorter than the default minimum length
+    fn dinucleotide_options() -> MotifOptions {
+        MotifOptions {
+            kmer_lengths: vec![2],
+            min_read_length: 2,
+            ..MotifOptions::default()
+        }
+    }
+
+    fn read_to_string(mut reader: Box<dyn BufRead>) -> String {
+        let mut content = String::new();
+        reader.read_to_string(&mut content).unwrap();
+        content
+    }
+
+    fn process(fastq: &str, options: &MotifOptions) -> ReadStats {
+        process_reads_and_write_fasta(
+            fastq.as_bytes(),
+            None,
+            None,
+            ReadSelection::First(usize::MAX),
+            0,
+            &Progress::Hidden,
+            options,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn open_fastq_reads_plain_input() {
+        let mut file = NamedTempFile::with_suffix(".fastq").unwrap();
+        file.write_all(FASTQ.as_bytes()).unwrap();
+        let reader = open_fastq(file.path().to_str().unwrap()).unwrap();
+        assert_eq!(read_to_string(reader), FASTQ);
+    }
+
+    #[test]
+    fn open_fastq_decompresses_gzip_input() {
+        let mut file = NamedTempFile::with_suffix(".fastq.gz").unwrap();
+        let mut encoder = GzEncoder::new(&mut file, GzCompression::default());
+        encoder.write_all(FASTQ.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+        let reader = open_fastq(file.path().to_str().unwrap()).unwrap();
+        assert_eq!(read_to_string(reader), FASTQ);
+    }
+
+    #[test]
+    fn open_fastq_reads_all_gzip_members() {
+        let mut file = NamedTempFile::with_suffix(".fastq.gz").unwrap();
+        let (first, second) = FASTQ.split_at(FASTQ.find("@homopolymer").unwrap());
+        for member in [first, second] {
+            let mut encoder = GzEncoder::new(&mut file, GzCompression::default());
+            encoder.write_all(member.as_bytes()).unwrap();
+            encoder.finish().unwrap();
+        }
+        let reader = open_fastq(file.path().to_str().unwrap()).unwrap();
+        assert_eq!(read_to_string(reader), FASTQ);
+    }
+
+    #[test]
+    fn open_fastq_detects_compression_whatever_the_extension() {
+        // Gzip named plain, and bzip2 named gzip
+        let mut gzip = NamedTempFile::with_suffix(".fastq").unwrap();
+        let mut encoder = GzEncoder::new(&mut gzip, GzCompression::default());
+        encoder.write_all(FASTQ.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+        let reader = open_fastq(gzip.path().to_str().unwrap()).unwrap();
+        assert_eq!(read_to_string(reader), FASTQ);
+
+        let mut bzip2 = NamedTempFile::with_suffix(".fastq.gz").unwrap();
+        let mut encoder = bzip2::write::BzEncoder::new(&mut bzip2, bzip2::Compression::default());
+        encoder.write_all(FASTQ.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+        let reader = open_fastq(bzip2.path().to_str().unwrap()).unwrap();
+        assert_eq!(read_to_string(reader), FASTQ);
+    }
+
+    #[test]
+    fn open_fastq_reads_empty_file_named_gzip() {
+        let file = NamedTempFile::with_suffix(".fastq.gz").unwrap();
+        let reader = open_fastq(file.path().to_str().unwrap()).unwrap();
+        assert_eq!(read_to_string(reader), "");
+    }
+
+    #[test]
+    fn open_fastq_reads_plain_input_named_gzip() {
+        let mut file = NamedTempFile::with_suffix(".fastq.gz").unwrap();
+        file.write_all(FASTQ.as_bytes()).unwrap();
+        let reader = open_fastq(file.path().to_str().unwrap()).unwrap();
+        assert_eq!(read_to_string(reader), FASTQ);
+    }
+
+    #[test]
+    fn process_reads_counts_motifs_of_each_read() {
+        let options = dinucleotide_options();
+        let stats = compute_motif_stats(&process(FASTQ, &options), &options);
+
+        // ATATATATATAT has 11 dinucleotides, 6 AT and 5 TA, over 15% of them, and ACGTTGCA has
+        // 7 different ones, each under 15%
+        assert_eq!(stats.total_reads, 3);
+        assert_eq!(stats.counts.get("AT"), Some(&1));
+        assert_eq!(stats.counts.get("TA"), Some(&1));
+        assert_eq!(stats.counts.get("AA"), Some(&1));
+        for motif in ["AC", "CG", "GT", "TT", "TG", "GC", "CA"] {
+            assert_eq!(
+                stats.counts.get(motif).copied().unwrap_or(0),
+                0,
+                "{}",
+                motif
+            );
+        }
+    }
+
+    #[test]
+    fn process_reads_counts_trinucleotides() {
+        let options = MotifOptions {
+            kmer_lengths: vec![3],
+            ..dinucleotide_options()
+        };
+        let stats = compute_motif_stats(&process(FASTQ, &options), &options);
+
+        // ATATATATATAT has 5 ATA and 5 TAT, and each of the 6 trinucleotides of ACGTTGCA makes
+        // up a sixth of them, above 15%
+        assert_eq!(stats.counts.get("ATA"), Some(&1));
+        assert_eq!(stats.counts.get("TAT"), Some(&1));
+        assert_eq!(stats.counts.get("AAA"), Some(&1));
+        for motif in ["ACG", "CGT", "GTT", "TTG", "TGC", "GCA"] {
+            assert_eq!(stats.counts.get(motif), Some(&1), "{}", motif);
+        }
+        assert_eq!(stats.counts.get("AT").copied(), None);
+        assert_eq!(stats.counts.len(), 4usize.pow(3) + 1);
+    }
+
+    #[test]
+    fn canonical_motifs_merge_reverse_complements() {
+        let options = MotifOptions {
+            kmer_lengths: vec![2, 3],
+            canonical: true,
+            ..dinucleotide_options()
+        };
+        let fastq = "@ct\nCTCTCTCTCTCT\n+\nIIIIIIIIIIII\n";
+        let stats = compute_motif_stats(&process(fastq, &options), &options);
+
+        // CT and TC stand for AG and GA, CTC and TCT for CTC and AGA
+        for motif in ["AG", "GA", "CTC", "AGA"] {
+            assert_eq!(stats.counts.get(motif), Some(&1), "{}", motif);
+        }
+        for motif in ["CT", "TC", "GAG", "TCT"] {
+            assert!(!stats.counts.contains_key(motif), "{}", motif);
+        }
+        // 10 of the 16 dinucleotides and 32 of the 64 trinucleotides, and LowComplexity
+        assert_eq!(stats.counts.len(), 10 + 32 + 1);
+        assert!(stats
+            .counts
+            .keys()
+            .all(|motif| motif == LOW_COMPLEXITY_MOTIF
+                || canonical_motif(motif.as_bytes(), Alphabet::Dna) == *motif));
+    }
+
+    #[test]
+    fn non_overlapping_motifs_step_by_their_length() {
+        let frequencies = |non_overlapping: bool| {
+            let options = MotifOptions {
+                kmer_lengths: vec![2, 3],
+                non_overlapping,
+                metric: Metric::MeanFrequency,
+                ..dinucleotide_options()
+            };
+            let fastq = "@read\nAATAATAAT\n+\nIIIIIIIII\n";
+            let stats = compute_motif_stats(&process(fastq, &options), &options);
+            ["AA", "AT", "TA", "AAT", "ATA"].map(|motif| {
+                stats
+                    .proportions
+                    .iter()
+                    .find(|(name, _)| name == motif)
+                    .map(|(_, proportion)| *proportion)
+                    .unwrap()
+            })
+        };
+
+        // Dinucleotides at 0, 2, 4 and 6 (AA, TA, AT and AA, 9 / 2 of them), and
+        // trinucleotides at 0, 3 and 6 (AAT three times, 9 / 3 of them)
+        assert_eq!(frequencies(true), [50.0, 25.0, 25.0, 100.0, 0.0]);
+        // Overlapping: 8 dinucleotides and 7 trinucleotides
+        let overlapping = frequencies(false);
+        assert_eq!(overlapping[..3], [37.5, 37.5, 25.0]);
+        assert!((overlapping[3] - 300.0 / 7.0).abs() < 1e-9);
+        assert!((overlapping[4] - 200.0 / 7.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn motifs_must_exceed_the_minimum_proportion() {
+        // ATATATATATAT has 6 AT and 5 TA out of 11 dinucleotides
+        let at_least = |min_proportion: f64| {
+            let options = MotifOptions {
+                min_proportion,
+                ..dinucleotide_options()
+            };
+            let stats = compute_motif_stats(&process(FASTQ, &options), &options);
+            ["AT", "TA"].map(|motif| stats.counts[motif])
+        };
+        assert_eq!(at_least(5.0 / 11.0), [1, 0]);
+        assert_eq!(at_least(5.0 / 11.0 - 1e-9), [1, 1]);
+        assert_eq!(at_least(6.0 / 11.0), [0, 0]);
+        assert_eq!(at_least(0.0), [1, 1]);
+    }
+
+    #[test]
+    fn process_reads_skips_the_first_reads() {
+        let options = dinucleotide_options();
+        let read_stats = process_reads_and_write_fasta(
+            FASTQ.as_bytes(),
+            None,
+            None,
+            ReadSelection::First(usize::MAX),
+            2,
+            &Progress::Hidden,
+            &options,
+        )
+        .unwrap();
+        let stats = compute_motif_stats(&read_stats, &options);
+        assert_eq!(stats.total_reads, 1);
+        assert_eq!(stats.counts.get("AT").copied().unwrap_or(0), 0);
+    }
+
+    #[test]
+    fn process_reads_writes_reads_named_by_index_for_sdust() {
+        let mut fasta = Vec::new();
+        process_reads_and_write_fasta(
+            FASTQ.as_bytes(),
+            None,
+            Some(&mut fasta),
+            ReadSelection::First(2),
+            0,
+            &Progress::Hidden,
+            &dinucleotide_options(),
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(fasta).unwrap(),
+            ">0\nATATATATATAT\n>1\nAAAAAAAAAAAA\n"
+        );
+    }
+
+    #[test]
+    fn process_reads_counts_reads_sharing_an_id() {
+        let options = MotifOptions {
+            read_names: true,
+            ..dinucleotide_options()
+        };
+        let fastq = FASTQ.replace("@homopolymer", "@repeat");
+        let mut fasta = Vec::new();
+        let read_stats = process_reads_and_write_fasta(
+            fastq.as_bytes(),
+            None,
+            Some(&mut fasta),
+            ReadSelection::First(usize::MAX),
+            0,
+            &Progress::Hidden,
+            &options,
+        )
+        .unwrap();
+
+        // Both reads keep their own entry, the FASTA for SDUST naming them by their index
+        assert_eq!(read_stats.total_reads, 3);
+        assert_eq!(read_stats.read_lengths, [12, 12, 8]);
+        assert!(String::from_utf8(fasta).unwrap().starts_with(">0\n"));
+        assert_eq!(duplicate_ids(&read_stats.read_names), 1);
+        let stats = compute_motif_stats(&read_stats, &options);
+        assert_eq!(stats.counts.get("AT"), Some(&1));
+        assert_eq!(stats.counts.get("AA"), Some(&1));
+    }
+
+    #[test]
+    fn random_selection_keeps_the_requested_reads() {
+        let fastq: String = (0..50)
+            .map(|read| format!("@r{}\nACGTACGTAC\n+\nIIIIIIIIII\n", read))
+            .collect();
+        let options = MotifOptions {
+            read_names: true,
+            ..dinucleotide_options()
+        };
+        let sample = |reads: usize, seed: u64| {
+            process_reads_and_write_fasta(
+                fastq.as_bytes(),
+                None,
+                None,
+                ReadSelection::Random {
+                    reads,
+                    seed: Some(seed),
+                },
+                0,
+                &Progress::Hidden,
+                &options,
+            )
+            .unwrap()
+        };
+
+        let stats = sample(10, 42);
+        assert_eq!(stats.total_reads, 10);
+        let names: HashSet<&String> = stats.read_names.iter().collect();
+        assert_eq!(names.len(), 10);
+        // The same seed draws the same reads, in the same order, and another one others
+        assert_eq!(sample(10, 42).read_names, stats.read_names);
+        assert_ne!(sample(10, 43).read_names, stats.read_names);
+        // All the reads are kept when fewer than requested
+        assert_eq!(sample(100, 42).total_reads, 50);
+    }
+
+    /// Second mates of the reads of [`FASTQ`]: two homopolymers and a read without any motif
+    /// above 15%
+    const FASTQ2: &str = "@repeat/2\nCCCCCCCCCCCC\n+\nIIIIIIIIIIII\n\
+                          @homopolymer/2\nAAAAAAAAAAAA\n+\nIIIIIIIIIIII\n\
+                          @mixed/2\nACGTTGCA\n+\nIIIIIIII\n";
+
+    fn process_pairs(
+        fastq: &str,
+        fastq2: Option<&str>,
+        skip_reads: usize,
+        options: &MotifOptions,
+    ) -> Result<ReadStats, AppError> {
+        process_reads_and_write_fasta(
+            fastq.as_bytes(),
+            fastq2.map(str::as_bytes),
+            None,
+            ReadSelection::First(usize::MAX),
+            skip_reads,
+            &Progress::Hidden,
+            options,
+        )
+    }
+
+    #[test]
+    fn paired_reads_are_counted_by_mate() {
+        let options = MotifOptions {
+            read_names: true,
+            ..dinucleotide_options()
+        };
+        let read_stats = process_pairs(FASTQ, Some(FASTQ2), 0, &options).unwrap();
+        assert_eq!(read_stats.total_reads, 6);
+        let stats = compute_motif_stats(&read_stats, &options);
+        let [r1, r2] = stats.mate_proportions.unwrap();
+        assert!((r1["AT"] - 100.0 / 3.0).abs() < 1e-9);
+        assert!((r1["AA"] - 100.0 / 3.0).abs() < 1e-9);
+        assert_eq!(r1["CC"], 0.0);
+        assert_eq!(r2["AT"], 0.0);
+        assert!((r2["AA"] - 100.0 / 3.0).abs() < 1e-9);
+        assert!((r2["CC"] - 100.0 / 3.0).abs() < 1e-9);
+
+        // The reads are skipped by pairs
+        let read_stats = process_pairs(FASTQ, Some(FASTQ2), 1, &options).unwrap();
+        assert_eq!(
+            read_stats.read_names,
+            ["homopolymer", "homopolymer/2", "mixed", "mixed/2"]
+        );
+    }
+
+    #[test]
+    fn paired_inputs_must_have_as_many_reads() {
+        let options = dinucleotide_options();
+        let (first_reads, _) = FASTQ2.split_at(FASTQ2.find("@mixed").unwrap());
+        assert!(matches!(
+            process_pairs(FASTQ, Some(first_reads), 0, &options),
+            Err(AppError::Input(_))
+        ));
+        assert!(matches!(
+            process_pairs(first_reads, Some(FASTQ), 0, &options),
+            Err(AppError::Input(_))
+        ));
+    }
+
+    #[test]
+    fn interleaved_reads_are_read_as_pairs() {
+        let options = MotifOptions {
+            interleaved: true,
+            read_names: true,
+            ..dinucleotide_options()
+        };
+        let records = |fastq: &'static str| fastq.split_inclusive('\n').collect::<Vec<_>>();
+        let (r1, r2) = (records(FASTQ), records(FASTQ2));
+        let interleaved: String = r1
+            .chunks(4)
+            .zip(r2.chunks(4))
+            .flat_map(|(mate1, mate2)| mate1.iter().chain(mate2))
+            .copied()
+            .collect();
+
+        let read_stats = process_pairs(&interleaved, None, 1, &options).unwrap();
+        assert_eq!(
+            read_stats.read_names,
+            ["homopolymer", "homopolymer/2", "mixed", "mixed/2"]
+        );
+        let paired_options = dinucleotide_options();
+        let paired = compute_motif_stats(
+            &process_pairs(FASTQ, Some(FASTQ2), 0, &paired_options).unwrap(),
+            &paired_options,
+        );
+        let stats = compute_motif_stats(
+            &process_pairs(&interleaved, None, 0, &options).unwrap(),
+            &options,
+        );
+        assert_eq!(stats.mate_proportions, paired.mate_proportions);
+
+        // A first mate without its second one
+        let odd = format!("{}{}", interleaved, r1[..4].concat());
+        assert!(matches!(
+            process_pairs(&odd, None, 0, &options),
+            Err(AppError::Input(_))
+        ));
+    }
+
+    #[test]
+    fn process_reads_keeps_the_read_ids() {
+        let options = MotifOptions {
+            read_names: true,
+            ..dinucleotide_options()
+        };
+        let fastq = FASTQ.replace("@repeat", "@repeat 1:N:0:ACGT");
+        let stats = process(&fastq, &options);
+        assert_eq!(
+            stats.read_names,
+            ["repeat 1:N:0:ACGT", "homopolymer", "mixed"]
+        );
+    }
+
+    #[test]
+    fn masked_fasta_keeps_the_read_names() {
+        let options = MotifOptions {
+            read_names: true,
+            dust_intervals: true,
+            ..dinucleotide_options()
+        };
+        let fastq = format!(
+            "@poly A\n{}\n+\n{}\n{}",
+            "A".repeat(60),
+            "I".repeat(60),
+            FASTQ
+        );
+        let mut fasta = Vec::new();
+        let stats = process_reads_and_write_fasta(
+            fastq.as_bytes(),
+            None,
+            Some(&mut fasta),
+            ReadSelection::First(2),
+            0,
+            &Progress::Hidden,
+            &options,
+        )
+        .unwrap();
+
+        // The built-in DUST still masks the reads written to the FASTA
+        assert_eq!(stats.masked_bases, [60, 12]);
+        let file = NamedTempFile::with_suffix(".fasta").unwrap();
+        save_masked_fasta(
+            file.path(),
+            fasta.as_slice(),
+            &stats.dust_intervals,
+            &stats.read_names,
+        )
+        .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(file.path()).unwrap(),
+            format!(
+                "{}\n{}\n{}\n",
+                ">poly A",
+                "a".repeat(60),
+                ">repeat\natatatatatat"
+            )
+        );
+    }
+
+    #[test]
+    fn longest_homopolymers_name_the_reads_by_id() {
+        let options = MotifOptions {
+            homopolymers: true,
+            read_names: true,
+            ..dinucleotide_options()
+        };
+        let stats = process(FASTQ, &options);
+        let file = NamedTempFile::with_suffix(".csv").unwrap();
+        save_longest_homopolymers(
+            file.path(),
+            stats.homopolymer_stats.as_ref().unwrap(),
+            &stats.read_names,
+        )
+        .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(file.path()).unwrap(),
+            "Read,Base,Length\nrepeat,A,1\nhomopolymer,A,12\nmixed,T,2\n"
+        );
+    }
+
+    #[test]
+    fn dust_bed_names_the_reads_by_id() {
+        let file = NamedTempFile::with_suffix(".bed").unwrap();
+        let names = ["poly A".to_string(), "mixed".to_string()];
+        save_dust_bed(file.path(), &[vec![(0, 60)], vec![], vec![(2, 5)]], &names).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(file.path()).unwrap(),
+            "poly\t0\t60\n2\t2\t5\n"
+        );
+    }
+
+    /// Checkpoints of a run reading stdin, saved after every read
+    fn checkpoint_options(path: &Path) -> CheckpointOptions {
+        CheckpointOptions {
+            path: path.to_path_buf(),
+            interval: std::time::Duration::ZERO,
+            inputs: vec!["-".to_string()],
+        }
+    }
+
+    #[test]
+    fn checkpoint_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let checkpoint_options = checkpoint_options(&dir.path().join("checkpoint.json"));
+        let stats = process(FASTQ, &dinucleotide_options());
+        checkpoint::save(&checkpoint_options, "options", 0, 3, 3, 3, &stats).unwrap();
+
+        let checkpoint = checkpoint::load(&checkpoint_options.path).unwrap().unwrap();
+        assert_eq!(
+            (
+                checkpoint.records,
+                checkpoint.selected,
+                checkpoint.examined_reads
+            ),
+            (3, 3, 3)
+        );
+        assert_eq!(checkpoint.stats.motif_counts, stats.motif_counts);
+        assert_eq!(checkpoint.stats.read_lengths, stats.read_lengths);
+        assert_eq!(checkpoint.stats.masked_bases, stats.masked_bases);
+        assert!(checkpoint.check(&checkpoint_options, 0, "options").is_ok());
+        assert!(checkpoint.check(&checkpoint_options, 1, "options").is_err());
+        assert!(checkpoint.check(&checkpoint_options, 0, "other").is_err());
+        let other_input = CheckpointOptions {
+            inputs: vec!["other.fastq".to_string()],
+            ..checkpoint_options
+        };
+        assert!(checkpoint.check(&other_input, 0, "options").is_err());
+    }
+
+    #[test]
+    fn resumed_run_counts_as_an_uninterrupted_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("checkpoint.json");
+        let options = MotifOptions {
+            checkpoint: Some(checkpoint_options(&path)),
+            ..dinucleotide_options()
+        };
+
+        // A run stopped after the first two reads, then resumed over the whole input
+        let (first_reads, _) = FASTQ.split_at(FASTQ.find("@mixed").unwrap());
+        process(first_reads, &options);
+        let resumed = compute_motif_stats(&process(FASTQ, &options), &options);
+        let uninterrupted = compute_motif_stats(
+            &process(FASTQ, &dinucleotide_options()),
+            &dinucleotide_options(),
+        );
+        assert_eq!(resumed.total_reads, 3);
+        assert_eq!(resumed.counts, uninterrupted.counts);
+        assert_eq!(resumed.proportions, uninterrupted.proportions);
+
+        // The checkpoint is not resumed from with other settings
+        let other_options = MotifOptions {
+            kmer_lengths: vec![3],
+            checkpoint: Some(checkpoint_options(&path)),
+            ..dinucleotide_options()
+        };
+        let result = process_reads_and_write_fasta(
+            FASTQ.as_bytes(),
+            None,
+            None,
+            ReadSelection::First(usize::MAX),
+            0,
+            &Progress::Hidden,
+            &other_options,
+        );
+        assert!(matches!(result, Err(AppError::Input(_))));
+    }
+
+    #[test]
+    fn motif_proportions_are_percentages_of_the_reads() {
+        let options = dinucleotide_options();
+        let stats = compute_motif_stats(&process(FASTQ, &options), &options);
+        let proportion = |motif: &str| {
+            stats
+                .proportions
+                .iter()
+                .find(|(name, _)| name == motif)
+                .map(|(_, proportion)| *proportion)
+        };
+        assert!((proportion("AT").unwrap() - 100.0 / 3.0).abs() < 1e-9);
+        assert!(stats
+            .proportions
+            .windows(2)
+            .all(|pair| pair[0].1 >= pair[1].1));
+    }
+
+    #[test]
+    fn motif_proportions_weighted_by_base() {
+        let options = MotifOptions {
+            weight: Weight::ByBase,
+            ..dinucleotide_options()
+        };
+        let stats = compute_motif_stats(&process(FASTQ, &options), &options);
+
+        // The 12 bases of the AT repeat out of the 32 bases of the reads
+        assert_eq!(stats.counts.get("AT"), Some(&12));
+        let (_, proportion) = stats
+            .proportions
+            .iter()
+            .find(|(motif, _)| motif == "AT")
+            .unwrap();
+        assert!((proportion - 37.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn motif_proportions_differ_by_read_and_by_base() {
+        let fastq = format!(
+            "@repeat\n{}\n+\n{}\n@mixed\nACGTTGCA\n+\nIIIIIIII\n",
+            "AT".repeat(20),
+            "I".repeat(40)
+        );
+        let proportion = |weight| {
+            let options = MotifOptions {
+                weight,
+                ..dinucleotide_options()
+            };
+            let stats = compute_motif_stats(&process(&fastq, &options), &options);
+            let proportion = |motif: &str| {
+                stats
+                    .proportions
+                    .iter()
+                    .find(|(name, _)| name == motif)
+                    .map(|(_, proportion)| *proportion)
+                    .unwrap()
+            };
+            (proportion("AT"), proportion(LOW_COMPLEXITY_MOTIF))
+        };
+
+        // One read out of two, but 40 bases out of 48, the repeat being masked too
+        let (by_read, low_complexity_by_read) = proportion(Weight::ByRead);
+        let (by_base, low_complexity_by_base) = proportion(Weight::ByBase);
+        assert!((by_read - 50.0).abs() < 1e-9);
+        assert!((by_base - 40.0 / 48.0 * 100.0).abs() < 1e-9);
+        assert!((low_complexity_by_read - 50.0).abs() < 1e-9);
+        assert!((low_complexity_by_base - 40.0 / 48.0 * 100.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn long_reads_weigh_their_analyzed_part_by_base() {
+        // Bases 850 to 1000 of a 1200-base read are analyzed, here an AT repeat
+        let long_read = format!(
+            "{}{}{}",
+            "ACGGT".repeat(170),
+            "AT".repeat(75),
+            "ACGGT".repeat(40)
+        );
+        let fastq = format!(
+            "@long\n{}\n+\n{}\n@mixed\nACGTTGCA\n+\nIIIIIIII\n",
+            long_read,
+            "I".repeat(long_read.len())
+        );
+        let options = MotifOptions {
+            weight: Weight::ByBase,
+            ..dinucleotide_options()
+        };
+        let read_stats = process(&fastq, &options);
+        assert_eq!(read_stats.read_lengths, [1200, 8]);
+        assert_eq!(read_stats.analyzed_lengths, [150, 8]);
+        assert_eq!(read_stats.masked_bases, [150, 0]);
+
+        // The masked window makes the long read low-complexity, for its 150 bases out of 158
+        let stats = compute_motif_stats(&read_stats, &options);
+        assert_eq!(stats.counts.get("AT"), Some(&150));
+        assert_eq!(stats.counts.get(LOW_COMPLEXITY_MOTIF), Some(&150));
+        assert!((stats.low_complexity_proportion - 150.0 / 158.0 * 100.0).abs() < 1e-9);
+
+        // The mean masked fraction of the bases is the same share of the analyzed bases
+        let options = MotifOptions {
+            metric: Metric::MeanFrequency,
+            ..options
+        };
+        let stats = compute_motif_stats(&process(&fastq, &options), &options);
+        assert!((stats.low_complexity_proportion - 150.0 / 158.0 * 100.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn chi_square_test_of_a_2x2_table() {
+        // 10 of 100 reads against 20 of 100: 200 * (10 * 80 - 90 * 20)^2 / (100 * 100 * 30 * 170)
+        let (chi_square, p_value) = chi_square_test(10, 100, 20, 100).unwrap();
+        assert!((chi_square - 3.9215686).abs() < 1e-6);
+        assert!((p_value - 0.0476704).abs() < 1e-6);
+
+        // Identical samples do not differ
+        let (chi_square, p_value) = chi_square_test(10, 100, 10, 100).unwrap();
+        assert_eq!(chi_square, 0.0);
+        assert!((p_value - 1.0).abs() < 1e-6);
+
+        // No test without the motif, with it in every read, or without reads
+        assert_eq!(chi_square_test(0, 100, 0, 50), None);
+        assert_eq!(chi_square_test(100, 100, 50, 50), None);
+        assert_eq!(chi_square_test(0, 0, 5, 10), None);
+        assert!((erfc(0.0) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn comparison_puts_both_samples_side_by_side() {
+        let options = dinucleotide_options();
+        let stats = compute_motif_stats(&process(FASTQ, &options), &options);
+        let other_stats = compute_motif_stats(&process(FASTQ2, &options), &options);
+        let file = NamedTempFile::with_suffix(".csv").unwrap();
+        save_comparison(file.path(), &stats, &other_stats, Units::Percent).unwrap();
+        let csv = std::fs::read_to_string(file.path()).unwrap();
+        let line = |motif: &str| {
+            csv.lines()
+                .find(|line| line.split(',').next() == Some(motif))
+                .unwrap()
+                .to_string()
+        };
+
+        // AT in 1 of 3 reads against none: 6 * 3^2 / (3 * 3 * 1 * 5)
+        assert!(line("AT").starts_with("AT,33.3333,1,3,0.0000,0,3,33.3333,1.2000,"));
+        assert!(line("CC").starts_with("CC,0.0000,0,3,33.3333,1,3,-33.3333,1.2000,"));
+        assert!(line("AA").starts_with("AA,33.3333,1,3,33.3333,1,3,0.0000,0.0000,"));
+        assert_eq!(line("GG"), "GG,0.0000,0,3,0.0000,0,3,0.0000,,");
+        // The largest differences come first
+        let motifs: Vec<&str> = csv
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').next().unwrap())
+            .collect();
+        assert_eq!(motifs[..3], ["AT", "CC", "TA"]);
+    }
+
+    #[test]
+    fn percentage_of_nothing_is_zero() {
+        assert_eq!(percentage(1, 4), 25.0);
+        assert_eq!(percentage(0, 0), 0.0);
+    }
+
+    #[test]
+    fn parse_dust_output_without_interval() {
+        assert_eq!(parse_dust_output("", 2).unwrap(), vec![0, 0]);
+    }
+
+    #[test]
+    fn parse_dust_output_single_interval() {
+        assert_eq!(parse_dust_output("1\t2\t6\n", 3).unwrap(), vec![0, 4, 0]);
+    }
+
+    #[test]
+    fn parse_dust_output_overlapping_intervals() {
+        assert_eq!(
+            parse_dust_output("0\t0\t9\n0\t5\t14\n", 1).unwrap(),
+            vec![14]
+        );
+        // Out of order, one inside another, and apart
+        assert_eq!(
+            parse_dust_output("0\t20\t24\n0\t0\t9\n0\t2\t4\n", 1).unwrap(),
+            vec![13]
+        );
+    }
+
+    #[test]
+    fn dust_intervals_are_half_open() {
+        // `sdust -w 64 -t 20` reports `0\t0\t64` for a read of 64 A, which is entirely masked
+        let sequence = [b'A'; 64];
+        assert_eq!(
+            dust::sdust(&sequence, DEFAULT_THRESHOLD, DEFAULT_WINDOW),
+            vec![(0, 64)]
+        );
+        assert_eq!(parse_dust_output("0\t0\t64\n", 1).unwrap(), vec![64]);
+    }
+
+    #[test]
+    fn parse_dust_output_leaves_out_other_lines() {
+        assert_eq!(parse_dust_output("0\t2\n\n0\t0\t1\n", 1).unwrap(), vec![1]);
+    }
+
+    #[test]
+    fn dust_intervals_must_lie_within_their_reads() {
+        let intervals = parse_dust_intervals("0\t0\t12\n1\t2\t6\n").unwrap();
+        assert_eq!(intervals, [vec![(0, 12)], vec![(2, 6)]]);
+        assert!(check_dust_intervals(&intervals, &[12, 8]).is_ok());
+        // Past the end of their read, reversed, or of an unknown read
+        for (intervals, read_lengths) in [
+            (vec![vec![(0, 13)]], vec![12]),
+            (vec![vec![], vec![(6, 2)]], vec![12, 8]),
+            (vec![vec![], vec![(0, 1)]], vec![12]),
+        ] {
+            assert!(matches!(
+                check_dust_intervals(&intervals, &read_lengths),
+                Err(AppError::Sdust(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn parse_dust_output_rejects_unknown_reads_and_positions() {
+        assert!(matches!(
+            parse_dust_output("2\t0\t1\n", 2),
+            Err(AppError::Sdust(_))
+        ));
+        assert!(matches!(
+            parse_dust_output("0\tx\t1\n", 1),
+            Err(AppError::Sdust(_))
+        ));
+    }
+}
@@ -1,14 +1,35 @@
-use clap::Parser;
-use flate2::read::GzDecoder;
-use std::collections::HashMap;
+use clap::parser::ValueSource;
+use clap::{ArgAction, ArgMatches, CommandFactory, Parser, ValueEnum};
+use freq_motif_fastq::checkpoint::CheckpointOptions;
+use freq_motif_fastq::dust::{DEFAULT_THRESHOLD, DEFAULT_WINDOW};
+use freq_motif_fastq::{
+    check_dust_intervals, compute_motif_stats, count_records, open_fastq, open_fastq_with_position,
+    parse_dust_intervals, parse_dust_output, plot, process_reads_and_write_fasta, read_dust_output,
+    read_motifs_file, save_base_composition, save_comparison, save_dinucleotide_composition,
+    save_dust_bed, save_entropy_histogram, save_flagged_motifs, save_gc_histogram,
+    save_homopolymer_stats, save_length_histogram, save_length_summary, save_longest_homopolymers,
+    save_low_complexity_distribution, save_masked_composition, save_masked_fasta,
+    save_max_motif_histogram, save_multiqc, save_per_read_dust, save_position_profile,
+    save_tile_stats, save_to_csv, save_to_json, AggregateStat, Alphabet, AmbiguousMotifs, AppError,
+    AtomicFile, CsvFormat, InputFormat, JsonMetadata, LengthBins, LongReads, Metric, MotifOptions,
+    MotifStats, OutputFile, Progress, ReadSelection, ReadStats, Units, Weight, AMBIGUOUS_MOTIF,
+    LOW_COMPLEXITY_MOTIF, LOW_ENTROPY_MOTIF, MAX_KMER_LENGTH, MIN_READ_LENGTH,
+};
+use log::{debug, error, info, warn, Level, LevelFilter};
+use regex::bytes::Regex;
+use serde_json::json;
 use std::env;
-use std::fs::{self, File};
-use std::io::{self, BufRead, BufReader, Write};
-use std::path::PathBuf;
-use std::process::Command;
+use std::ffi::OsString;
+use std::fs;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
 
-/// Analyze FASTQ files (including gzip) and generate statistics on motifs and low-complexity bases
+/// Analyze FASTQ files (including gzip, bzip2 and zstd) and generate statistics on motifs and low-complexity bases
 #[derive(Parser, Debug)]
 #[command(
     author = "Jean-Marc Aury", 
@@ -18,302 +39,2452 @@ use uuid::Uuid;
     after_help = "Additional Information:\nCould be short- or long-reads, but only the first 150 base pairs of each read will be used.\nIf a read is longer than 1,000 base pairs, the 150 base pairs between positions 850 and 1,000 will be used instead."
 )]
 struct Args {
-    /// Input FASTQ file (supports gzip)
-    #[arg(short, long)]
-    input: String,
+    /// Input FASTQ file(s) (supports gzip, bzip2 and zstd), or `-` to read from stdin
+    #[arg(
+        short,
+        long,
+        num_args = 1..,
+        required_unless_present_any = ["input_list", "doctor", "list_formats"]
+    )]
+    input: Vec<String>,
+
+    /// File listing the input FASTQ files, one per line, optionally followed by the file of
+    /// their second mates
+    #[arg(long)]
+    input_list: Option<String>,
+
+    /// FASTQ file(s) of the second mates of paired-end reads, read in lockstep with the inputs
+    #[arg(long, num_args = 1..)]
+    input2: Vec<String>,
+
+    /// Read the consecutive records of each input as the two mates of paired-end reads
+    #[arg(long, conflicts_with = "input2")]
+    interleaved: bool,
+
+    /// Save the proportions of the reads of each Illumina tile, read from their names, to
+    /// `tile_stats.csv`
+    #[arg(long)]
+    by_tile: bool,
+
+    /// Add the proportions of each mate of paired-end reads to the results
+    #[arg(long)]
+    per_mate: bool,
+
+    /// Also analyze this FASTQ file, e.g. a control, with the same options, and save the
+    /// proportions of both side by side, with their difference and a chi-square test, to
+    /// `comparison.csv`
+    #[arg(
+        long,
+        value_name = "FASTQ",
+        conflicts_with_all = ["input2", "metric", "aggregate_stat", "checkpoint", "emit_masked_fasta", "emit_bed"]
+    )]
+    compare: Option<String>,
 
-    /// Output directory to save results (default: unique directory in current directory)
+    /// Aggregate the counts of all the inputs instead of saving the results of each input to
+    /// its own subdirectory
+    #[arg(long)]
+    aggregate: bool,
+
+    /// Format of the input records: auto (FASTA if the first record starts with '>'), fastq
+    /// or fasta
+    #[arg(long, value_enum, default_value_t = InputFormat::Auto)]
+    input_format: InputFormat,
+
+    /// Fail on a FASTQ record whose quality length differs from its sequence length, a sign of a
+    /// truncated or corrupted file, instead of skipping it
+    #[arg(long)]
+    validate_quality_length: bool,
+
+    /// Output directory to save results (default: `freq_motif_<name of the first input>` in the
+    /// current directory), where `{sample}` is replaced by the name of the sample, or `-` to
+    /// write the results CSV alone to stdout
     #[arg(short, long)]
     output_dir: Option<String>,
 
-    /// Maximum number of reads to analyze
-    #[arg(short, long, default_value_t = 100_000)]
+    /// Name of the sample, written to the results files and naming the output directory
+    /// (default: the name of the input). Not available with several inputs unless aggregated
+    #[arg(long, value_parser = parse_sample_name)]
+    sample_name: Option<String>,
+
+    /// Name the default output directory with a random UUID instead of the name of the input
+    #[arg(long, conflicts_with = "output_dir")]
+    random_output_name: bool,
+
+    /// Write to the default output directory even if it already exists, replacing the files of
+    /// the previous run
+    #[arg(long)]
+    force: bool,
+
+    /// Maximum number of reads to analyze, `0` or `all` to analyze all of them
+    #[arg(short, long, default_value_t = 100_000, value_parser = parse_max_reads)]
     max_reads: usize,
 
     /// Minimum proportion to consider (in percentage).
     /// Be aware that changing this value will affect the readability of the output graph.
-    #[arg(short, long, default_value_t = 15.0)]
+    #[arg(short, long, default_value_t = 15.0, value_parser = parse_ratio)]
     ratio: f64,
 
+    /// Minimum proportion of the dinucleotides in a read (in percentage), instead of `--ratio`
+    #[arg(long = "ratio-2", value_name = "RATIO", value_parser = parse_ratio)]
+    ratio_2: Option<f64>,
+
+    /// Minimum proportion of the trinucleotides in a read (in percentage), instead of `--ratio`
+    #[arg(long = "ratio-3", value_name = "RATIO", value_parser = parse_ratio)]
+    ratio_3: Option<f64>,
+
     /// Number of initial reads to skip
     #[arg(short = 'S', long, default_value_t = 10_000)]
     skip: usize,
-}
 
-fn main() {
-    let args = Args::parse();
-
-    let input_file = args.input;
-    let max_reads = args.max_reads;
-    let min_proportion = args.ratio / 100.0;
-    let skip_reads = args.skip;
-
-    // Determine output directory
-    let output_dir = match args.output_dir {
-        Some(dir) => PathBuf::from(dir),
-        None => {
-            let current_dir = env::current_dir().expect("Failed to get current directory");
-            current_dir.join(format!("freq_motif_{}", Uuid::new_v4()))
-        }
-    };
+    /// Fraction of the reads of each input to skip at its start, between 0 and 1, instead of
+    /// `--skip`. The reads are counted in a first pass over the input.
+    #[arg(long, value_name = "FRACTION", value_parser = parse_fraction, conflicts_with = "skip")]
+    skip_fraction: Option<f64>,
 
-    // Create the output directory if it doesn't exist
-    fs::create_dir_all(&output_dir).expect("Failed to create output directory");
+    /// Comma-separated list of motif lengths to count, from 1 to 12
+    #[arg(
+        short,
+        long,
+        value_delimiter = ',',
+        default_value = "2,3",
+        value_parser = parse_kmer_length
+    )]
+    kmer: Vec<usize>,
 
-    let output_csv = output_dir.join("freq-motif.csv");
-    let output_graph = output_dir.join("barplot_freq-motif.png");
-    let fasta_file = output_dir.join("temp.fasta");
+    /// File of the motifs to count instead of all the motifs of the `--kmer` lengths, one per
+    /// line (lines starting with '#' are ignored)
+    #[arg(long, value_name = "FILE", conflicts_with = "kmer")]
+    motifs_file: Option<PathBuf>,
 
-    eprintln!("Opening the input file: {}", input_file);
-    let reader = open_fastq(&input_file).expect("Error opening the FASTQ file");
+    /// Format of the results file(s)
+    #[arg(long, value_enum, default_value_t = OutputFormat::Csv)]
+    format: OutputFormat,
 
-    eprintln!("Skipping the first {} reads...", skip_reads);
-    let (motif_counts, total_reads, read_lengths) =
-        process_reads_and_write_fasta(reader, &fasta_file, max_reads, min_proportion, skip_reads);
+    /// Also save the key metrics of the sample to `<sample>_freq_motif_mqc.tsv`, which MultiQC
+    /// picks up as a section of its report
+    #[arg(long)]
+    multiqc: bool,
 
-    eprintln!("Running SDUST...");
-    let status = Command::new("sdust")
-        .arg(&fasta_file)
-        .output()
-        .expect("Failed to execute SDUST");
+    /// Only save and plot the N motifs of highest proportion, and LowComplexity
+    #[arg(long, value_name = "N")]
+    top: Option<usize>,
 
-    if !status.status.success() {
-        eprintln!("SDUST execution failed.");
-        std::process::exit(1);
-    }
+    /// Fail the QC of a sample whose LowComplexity proportion is above this percentage
+    #[arg(long, value_name = "PERCENT", value_parser = parse_ratio)]
+    fail_if_low_complexity: Option<f64>,
+
+    /// Fail the QC of a sample in which a motif, not a category of reads, has a proportion above
+    /// this percentage
+    #[arg(long, value_name = "PERCENT", value_parser = parse_ratio)]
+    fail_if_motif: Option<f64>,
+
+    /// Exit code of a run whose outputs are all saved but in which a sample failed the QC
+    #[arg(long, value_name = "CODE", default_value_t = 10, value_parser = clap::value_parser!(i32).range(1..=255))]
+    qc_fail_code: i32,
+
+    /// Separator of the fields of the results CSV file: a single character, or `tab`
+    #[arg(long, default_value = ",", value_parser = parse_delimiter, conflicts_with = "use_r_script")]
+    delimiter: char,
+
+    /// Unit of the proportions saved to the results files: percent, in a `Proportion` column, or
+    /// fraction, in a `Fraction` column
+    #[arg(long, value_enum, default_value_t = Units::Percent, conflicts_with = "use_r_script")]
+    units: Units,
+
+    /// Do not write the names of the columns on the first line of the results CSV file
+    #[arg(long, conflicts_with = "use_r_script")]
+    no_header: bool,
+
+    /// Count each motif together with its reverse complement, under the lexicographically smaller of the two
+    #[arg(long)]
+    canonical: bool,
+
+    /// Also count the motifs of the reverse complement of each read, over twice the windows, for
+    /// double-stranded (e.g. genomic) libraries. The other statistics stay on the read itself
+    #[arg(long)]
+    both_strands: bool,
+
+    /// Count motifs on consecutive, non-overlapping windows (positions 0, k, 2k...) instead of at
+    /// every position
+    #[arg(long)]
+    non_overlapping: bool,
+
+    /// Bases of the reads, which the counted motifs are made of: dna (A/C/G/T) or rna (A/C/G/U)
+    #[arg(long, value_enum, default_value_t = Alphabet::Dna)]
+    alphabet: Alphabet,
+
+    /// Keep lowercase (soft-masked) bases distinct from uppercase ones instead of uppercasing sequences
+    #[arg(long)]
+    preserve_case: bool,
+
+    /// How motifs containing N or other ambiguity codes are handled: skipped (excluded from the
+    /// counts and denominators) or bucketed into a single `Ambiguous` category
+    #[arg(long, value_enum, default_value_t = AmbiguousMotifs::Skip)]
+    ambiguous: AmbiguousMotifs,
+
+    /// Value reported for each motif: the percentage of the reads in which it exceeds `--ratio`
+    /// (presence) or its mean percentage of the motifs of the same length of a read
+    /// (mean-frequency)
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = Metric::Presence,
+        conflicts_with_all = ["length_bins", "per_mate"]
+    )]
+    metric: Metric,
+
+    /// Weight of each read in the proportions: every read counting the same (by-read), or as
+    /// many times as it has bases in its analyzed part (by-base), where its motifs and masked
+    /// bases are found, for short reads to weigh less than long ones
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = Weight::ByRead,
+        conflicts_with_all = ["aggregate_stat", "length_bins", "per_mate", "by_tile", "compare"]
+    )]
+    weight: Weight,
+
+    /// Report, for each motif, this statistic of its percentage of the motifs of the same length
+    /// of each read, and for LowComplexity of the masked percentage of each read. The median and
+    /// p90 keep the frequencies of every read in memory.
+    #[arg(
+        long,
+        value_enum,
+        conflicts_with_all = ["metric", "length_bins", "per_mate"]
+    )]
+    aggregate_stat: Option<AggregateStat>,
+
+    /// Only analyze the reads whose name (their header without the leading `@` or `>`) matches
+    /// this regular expression
+    #[arg(long, value_name = "REGEX")]
+    name_filter: Option<Regex>,
+
+    /// Minimum length of the reads, once trimmed, the shorter ones being skipped, e.g. to leave
+    /// out adapter dimers
+    #[arg(long, value_name = "N", default_value_t = MIN_READ_LENGTH, value_parser = parse_min_read_length)]
+    min_read_length: usize,
+
+    /// Maximum length of the reads, once trimmed, to bound the memory used by ultra-long reads
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    max_read_length: Option<u64>,
+
+    /// How the reads longer than `--max-read-length` are handled: truncated to it, which is also
+    /// the length then reported, or skipped
+    #[arg(long, value_enum, default_value_t = LongReads::Truncate, requires = "max_read_length")]
+    long_reads: LongReads,
+
+    /// Memory in MB the counts may take, above which a warning is given, to stop a run before
+    /// it gets killed on a shared node. The counts grow with the number of reads and of motifs
+    #[arg(long, value_name = "MB", value_parser = clap::value_parser!(u64).range(1..))]
+    max_memory: Option<u64>,
+
+    /// Analyze a uniform random sample of this many reads taken across the whole file,
+    /// instead of the first `--max-reads` reads
+    #[arg(long, value_name = "N")]
+    random: Option<usize>,
+
+    /// Seed of all the random choices of the analysis (the `--random` sampling), drawn at
+    /// random and reported when not given, for reproducible results
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Comma-separated upper bounds of read-length bins (e.g. 100,500 for 0-100, 100-500 and 500+)
+    /// used to break down the proportions by read length in the CSV
+    #[arg(long, value_delimiter = ',')]
+    length_bins: Vec<usize>,
+
+    /// Comma-separated upper bounds of the buckets of the read-length histogram
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "100,150,200,300,500,1000,2000,5000,10000,20000,50000"
+    )]
+    length_hist_bins: Vec<usize>,
+
+    /// Do not compute the GC content of the reads
+    #[arg(long)]
+    no_gc: bool,
+
+    /// Comma-separated motifs whose occurrence at each position of the analyzed part of the
+    /// reads is saved to `position_profile.csv`
+    #[arg(long, value_name = "MOTIFS", value_delimiter = ',', value_parser = parse_motif)]
+    position_profile: Vec<String>,
+
+    /// Save the masked bases, length and masked fraction of each read, named by its id
+    /// (its name up to the first whitespace), to `per_read_dust.tsv`
+    #[arg(long)]
+    per_read_dust: bool,
+
+    /// Save the number of homopolymer runs of each length and base to `homopolymer_stats.csv`
+    #[arg(long)]
+    homopolymers: bool,
+
+    /// Save the longest homopolymer run of each read, named by its id, to `max_homopolymer.csv`
+    #[arg(long)]
+    max_homopolymer: bool,
+
+    /// Save the occurrences of each motif inside the low-complexity regions masked by the
+    /// built-in DUST and in the rest of the reads to `masked_composition.csv`
+    #[arg(long, conflicts_with = "use_external_sdust")]
+    masked_composition: bool,
 
-    let dust_output_data = String::from_utf8(status.stdout).expect("Failed to read SDUST output");
-    let low_complexity_reads = parse_dust_output(&dust_output_data, &read_lengths, min_proportion);
+    /// Save the fraction of each of the 16 dinucleotides among all the dinucleotides of the
+    /// reads to `dinucleotide_composition.csv`
+    #[arg(long)]
+    dinucleotide_composition: bool,
 
-    eprintln!("Sorting results...");
+    /// Add the log2 ratio of the observed frequency of each motif over its frequency expected
+    /// from the base composition of the reads
+    #[arg(long)]
+    enrichment: bool,
 
-    let mut proportions: HashMap<String, f64> = initialize_all_motifs();
+    /// Count the reads whose Shannon entropy over their bases is below this value, in bits
+    /// (0 to 2), in a LowEntropy category, and save the entropy histogram to
+    /// `entropy_histogram.csv`
+    #[arg(long, value_name = "BITS", value_parser = parse_entropy)]
+    min_entropy: Option<f64>,
 
-    for (motif, count) in motif_counts {
-        proportions.insert(motif.clone(), (count as f64 / total_reads as f64) * 100.0);
+    /// Number of bases trimmed from the start of each read before analysis
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    trim_start: usize,
+
+    /// Number of bases trimmed from the end of each read before analysis
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    trim_end: usize,
+
+    /// Skip reads whose mean Phred quality is below this value
+    #[arg(long, value_name = "QUAL")]
+    min_mean_qual: Option<f64>,
+
+    /// Offset of the ASCII encoding of the quality scores (33 or 64)
+    #[arg(long, value_name = "OFFSET", default_value_t = 33, value_parser = parse_phred_offset)]
+    phred_offset: u8,
+
+    /// Number of threads used to count motifs (0 uses all available CPUs)
+    #[arg(short, long, default_value_t = 0)]
+    threads: usize,
+
+    /// Detect low-complexity regions with the external `sdust` binary instead of the built-in implementation
+    #[arg(long)]
+    use_external_sdust: bool,
+
+    /// Window size of DUST, built-in or external
+    #[arg(long, value_name = "W", default_value_t = DEFAULT_WINDOW, value_parser = parse_dust_window)]
+    dust_window: usize,
+
+    /// Score threshold of DUST, built-in or external
+    #[arg(long, value_name = "T", default_value_t = DEFAULT_THRESHOLD, value_parser = clap::value_parser!(i64).range(1..))]
+    dust_threshold: i64,
+
+    /// Extra options given to the external `sdust`, e.g. "-x 5", split on whitespace and passed
+    /// as they are, without a shell. The window and threshold are set with `--dust-window` and
+    /// `--dust-threshold`
+    #[arg(long, value_name = "ARGS", value_parser = parse_sdust_args, requires = "use_external_sdust", allow_hyphen_values = true)]
+    sdust_args: Option<SdustArgs>,
+
+    /// Pipe the reads to the external `sdust` and parse its output as it comes, instead of
+    /// writing them to a temporary FASTA file first
+    #[arg(long, requires = "use_external_sdust", conflicts_with = "keep_temp")]
+    stream_sdust: bool,
+
+    /// Go on with the output SDUST produced when it fails, the reads missing from it being
+    /// counted as unmasked
+    #[arg(long, requires = "use_external_sdust")]
+    tolerate_sdust_failure: bool,
+
+    /// Save the analyzed part of the reads, named as in the input, with the regions masked by
+    /// the built-in DUST or the external `sdust` lowercased to `masked.fasta`
+    #[arg(long, conflicts_with_all = ["stream_sdust", "aggregate"])]
+    emit_masked_fasta: bool,
+
+    /// Save the regions masked by the built-in DUST or the external `sdust` in the analyzed part
+    /// of the reads, named by their id, to `low_complexity.bed`
+    #[arg(long, conflicts_with_all = ["stream_sdust", "aggregate"])]
+    emit_bed: bool,
+
+    /// Keep the temporary FASTA file given to the external `sdust`, for debugging
+    #[arg(long, requires = "use_external_sdust")]
+    keep_temp: bool,
+
+    /// Gzip the temporary FASTA file kept with `--keep-temp` and the per-read output files
+    /// (`max_homopolymer.csv` and `per_read_dust.tsv`)
+    #[arg(long)]
+    compress_output: bool,
+
+    /// Draw the barplot with the `generate_barplot.R` script (requires R and ggplot2) instead of
+    /// the built-in renderer
+    #[arg(long)]
+    use_r_script: bool,
+
+    /// Do not draw the barplot, e.g. on nodes where only the CSV is needed
+    #[arg(long, conflicts_with_all = ["use_r_script", "plot_kmer"])]
+    no_plot: bool,
+
+    /// Only plot the motifs of these lengths, next to LowComplexity and the other categories,
+    /// for the proportions of motifs of different lengths not to share an axis
+    #[arg(long, num_args = 1.., value_delimiter = ',', value_parser = parse_kmer_length)]
+    plot_kmer: Vec<usize>,
+
+    /// Only check that the inputs open, that the output directory is writable and that the
+    /// external programs are found, then exit with 0 if everything needed is ready, 1 otherwise
+    #[arg(long, alias = "dry-run")]
+    check: bool,
+
+    /// Report the version of the tool and whether the external programs are found, with their
+    /// versions, then exit
+    #[arg(long, exclusive = true)]
+    doctor: bool,
+
+    /// Print the supported input formats and compressions, then exit
+    #[arg(long, exclusive = true)]
+    list_formats: bool,
+
+    /// TOML file of options, keyed by their long names, that the ones of the command line
+    /// override
+    #[arg(long, value_name = "TOML")]
+    config: Option<PathBuf>,
+
+    /// Only print errors
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Also print the steps of the analysis and the progress of the reading
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Number of reads between two progress messages printed with `--verbose` when stderr is not
+    /// a terminal, which shows a progress bar instead (0 disables both)
+    #[arg(long, default_value_t = 10_000)]
+    progress_interval: usize,
+
+    /// Save the counts periodically to this file, and resume from it when it exists, for an
+    /// interrupted run to go on where it stopped. It is removed once the results are saved
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["random", "use_external_sdust", "aggregate", "emit_masked_fasta"]
+    )]
+    checkpoint: Option<PathBuf>,
+
+    /// Minimum number of seconds between two checkpoints
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 60,
+        requires = "checkpoint"
+    )]
+    checkpoint_interval: u64,
+}
+
+/// Formats in which the results can be saved
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Csv,
+    Json,
+    Both,
+}
+
+/// Parses a motif length, rejecting zero and the lengths of too many motifs to list them all
+fn parse_kmer_length(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(0) => Err("motif length must be at least 1".to_string()),
+        Ok(k) if k > MAX_KMER_LENGTH => Err(format!(
+            "motif length must be at most {}, use --motifs-file for longer motifs",
+            MAX_KMER_LENGTH
+        )),
+        Ok(k) => Ok(k),
+        Err(e) => Err(e.to_string()),
     }
+}
 
-    // Add low-complexity result
-    let low_complexity_proportion = (low_complexity_reads as f64 / total_reads as f64) * 100.0;
-    proportions.insert("LowComplexity".to_string(), low_complexity_proportion);
+/// Parses a maximum number of reads, `all` standing for 0, which sets no limit
+fn parse_max_reads(s: &str) -> Result<usize, String> {
+    match s {
+        "all" => Ok(0),
+        _ => s.parse::<usize>().map_err(|e| e.to_string()),
+    }
+}
 
-    // Sort by proportion descending
-    let mut sorted_proportions: Vec<(String, f64)> = proportions.into_iter().collect();
-    sorted_proportions.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+/// Parses a sample name, rejecting the ones that cannot name a directory
+fn parse_sample_name(s: &str) -> Result<String, String> {
+    if s.is_empty() || s == "." || s == ".." || s.contains(['/', '\\']) {
+        return Err("sample name must be a non-empty file name".to_string());
+    }
+    Ok(s.to_string())
+}
 
-    eprintln!("Saving results to CSV: {}", output_csv.display());
-    save_to_csv(&output_csv, &sorted_proportions).expect("Error saving the CSV file");
+/// Parses a minimum read length, rejecting the ones too short to hold a dinucleotide
+fn parse_min_read_length(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(length) if length < 2 => Err("minimum read length must be at least 2".to_string()),
+        Ok(length) => Ok(length),
+        Err(e) => Err(e.to_string()),
+    }
+}
 
-    eprintln!("Running R script for barplot...");
-    let status = Command::new("generate_barplot.R")
-        .arg(output_csv.as_os_str())
-        .arg(output_graph.as_os_str())
-        .arg(args.ratio.to_string()) // Pass the -r parameter
-        .status()
-        .expect("Failed to run Rscript");
+/// Parses a percentage, rejecting values outside 0-100
+fn parse_ratio(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(ratio) if (0.0..=100.0).contains(&ratio) => Ok(ratio),
+        Ok(_) => Err("ratio must be a percentage between 0 and 100".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
 
-    if !status.success() {
-        eprintln!("Rscript execution failed");
-        std::process::exit(1);
+/// Parses a fraction, between 0 and 1
+fn parse_fraction(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(fraction) if (0.0..=1.0).contains(&fraction) => Ok(fraction),
+        Ok(_) => Err("fraction must be between 0 and 1".to_string()),
+        Err(e) => Err(e.to_string()),
     }
+}
 
-    eprintln!("Cleaning up temporary files...");
-    fs::remove_file(&fasta_file).expect("Failed to remove temporary FASTA file");
+/// Parses an entropy of a base, in bits, between 0 and 2
+fn parse_entropy(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(entropy) if (0.0..=2.0).contains(&entropy) => Ok(entropy),
+        Ok(_) => Err("entropy must be between 0 and 2 bits".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
 
-    eprintln!(
-        "Analysis completed successfully. Results saved to '{}' and '{}'.",
-        output_csv.display(),
-        output_graph.display()
-    );
+/// Parses a field separator, a single character or `tab`
+fn parse_delimiter(s: &str) -> Result<char, String> {
+    let mut chars = s.chars();
+    match (s, chars.next(), chars.next()) {
+        ("tab" | "\\t", _, _) => Ok('\t'),
+        (_, Some(delimiter), None) => Ok(delimiter),
+        _ => Err("delimiter must be a single character or 'tab'".to_string()),
+    }
 }
 
-/// Initializes all possible motifs with 0 proportions
-fn initialize_all_motifs() -> HashMap<String, f64> {
-    let mut all_motifs = HashMap::new();
-    let bases = ["A", "T", "G", "C"];
+/// Extra options of `sdust`, one per argument
+#[derive(Debug, Clone)]
+struct SdustArgs(Vec<String>);
 
-    for &b1 in &bases {
-        for &b2 in &bases {
-            all_motifs.insert(format!("{}{}", b1, b2), 0.0);
+/// Splits the extra options of `sdust` on whitespace, rejecting the window and threshold, set by
+/// their own options, and the values following no option, which `sdust` would read as inputs
+fn parse_sdust_args(s: &str) -> Result<SdustArgs, String> {
+    let arguments: Vec<String> = s.split_whitespace().map(str::to_string).collect();
+    let is_option = |argument: &str| argument.len() > 1 && argument.starts_with('-');
+    for (index, argument) in arguments.iter().enumerate() {
+        if is_option(argument) {
+            if argument.starts_with("-w") || argument.starts_with("-t") {
+                return Err(format!(
+                    "{} is set with --dust-window or --dust-threshold",
+                    argument
+                ));
+            }
+        } else if index == 0 || !is_option(&arguments[index - 1]) {
+            return Err(format!(
+                "{} follows no option, and would be read by sdust as an input",
+                argument
+            ));
         }
     }
+    Ok(SdustArgs(arguments))
+}
 
-    for &b1 in &bases {
-        for &b2 in &bases {
-            for &b3 in &bases {
-                all_motifs.insert(format!("{}{}{}", b1, b2, b3), 0.0);
-            }
+/// Parses a motif, rejecting anything but letters
+fn parse_motif(s: &str) -> Result<String, String> {
+    if s.is_empty() || !s.bytes().all(|base| base.is_ascii_alphabetic()) {
+        return Err(format!("invalid motif '{}'", s));
+    }
+    Ok(s.to_string())
+}
+
+/// Parses a DUST window size, which must hold at least one triplet
+fn parse_dust_window(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(window) if window >= 3 => Ok(window),
+        Ok(_) => Err("the DUST window must be at least 3".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Parses a Phred quality offset, accepting only the two standard ones
+fn parse_phred_offset(s: &str) -> Result<u8, String> {
+    match s.parse::<u8>() {
+        Ok(offset @ (33 | 64)) => Ok(offset),
+        Ok(_) => Err("the Phred offset must be 33 or 64".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn main() {
+    let start = Instant::now();
+    let (mut args, arguments) = parse_args();
+    args.seed.get_or_insert_with(rand::random);
+    init_logger(&args);
+
+    if args.doctor {
+        doctor();
+        return;
+    }
+    if args.list_formats {
+        list_formats();
+        return;
+    }
+    if args.check {
+        std::process::exit(if check(&args) { 0 } else { 1 });
+    }
+
+    let qc_fail_code = args.qc_fail_code;
+    let result = run(args, &arguments);
+    match &result {
+        Ok((total_reads, _)) => {
+            let elapsed = start.elapsed().as_secs_f64();
+            info!(
+                "Elapsed time: {:.2} s ({:.0} reads/s)",
+                elapsed,
+                *total_reads as f64 / elapsed
+            );
         }
+        Err(e) => error!("{}", e),
+    }
+    let exit_code = exit_code(&result, qc_fail_code);
+    if exit_code != 0 {
+        std::process::exit(exit_code);
     }
+}
 
-    all_motifs
+/// Exit code of a run: that of its error, `qc_fail_code` when a sample failed the QC, 0 otherwise
+fn exit_code(result: &Result<(usize, bool), AppError>, qc_fail_code: i32) -> i32 {
+    match result {
+        Ok((_, true)) => 0,
+        Ok((_, false)) => qc_fail_code,
+        Err(e) => e.exit_code(),
+    }
 }
 
-/// Opens a FASTQ file, handling both plain and gzipped formats
-fn open_fastq(filename: &str) -> io::Result<Box<dyn BufRead>> {
-    if filename.ends_with(".gz") {
-        let file = File::open(filename)?;
-        let decoder = GzDecoder::new(file);
-        Ok(Box::new(BufReader::new(decoder)))
-    } else {
-        let file = File::open(filename)?;
-        Ok(Box::new(BufReader::new(file)))
+/// Parses the command line, completed by the options of the `--config` file it gives, if any,
+/// returning the arguments it was parsed from
+fn parse_args() -> (Args, Vec<OsString>) {
+    match with_config_arguments(env::args_os().collect()) {
+        Ok(arguments) => (Args::parse_from(&arguments), arguments),
+        Err(e) => Args::command()
+            .error(clap::error::ErrorKind::InvalidValue, e)
+            .exit(),
     }
 }
 
-/// Processes reads, writes to a temporary FASTA file, and counts motifs
-fn process_reads_and_write_fasta<R: BufRead>(
-    reader: R,
-    fasta_file: &PathBuf,
-    max_reads: usize,
-    min_proportion: f64,
-    skip_reads: usize,
-) -> (HashMap<String, u64>, usize, HashMap<String, usize>) {
-    let mut motif_counts: HashMap<String, u64> = HashMap::new();
-    let mut total_reads = 0;
-    let mut read_lengths: HashMap<String, usize> = HashMap::new();
-    let mut fasta_writer = File::create(fasta_file).expect("Failed to create temporary FASTA file");
-
-    let mut lines = reader.lines();
-
-    // Skip the first `skip_reads` reads
-    for _ in 0..skip_reads {
-        lines.next();
-        lines.next();
-        lines.next();
-        lines.next();
-    }
-
-    // Length to take into account
-    let processing_length = 150;
-    // Minimal length to take into account 150bp from position (1000-150) instead of first position
-    let sequence_length_threshold = 1000;
-
-    while let Some(Ok(header)) = lines.next() {
-        if let Some(Ok(sequence)) = lines.next() {
-            if total_reads >= max_reads {
-                break;
-            }
+/// The command line followed by the arguments of its config file, if any.
+fn with_config_arguments(command_line: Vec<OsString>) -> Result<Vec<OsString>, String> {
+    // A first lenient pass finds the config file and the options of the command line,
+    // the required ones possibly being in the file
+    let matches = Args::command()
+        .ignore_errors(true)
+        .get_matches_from(&command_line);
+    let Some(config) = matches.get_one::<PathBuf>("config") else {
+        return Ok(command_line);
+    };
+    let config_arguments = config_arguments(config, &matches)?;
+    Ok(command_line.into_iter().chain(config_arguments).collect())
+}
+
+/// Command-line arguments of the options of a TOML config file, leaving out the ones given
+/// on the command line. Flags are set by `true`, and options taking several values by arrays.
+fn config_arguments(config: &Path, matches: &ArgMatches) -> Result<Vec<OsString>, String> {
+    let content = fs::read_to_string(config)
+        .map_err(|e| format!("failed to read {}: {}", config.display(), e))?;
+    let table: toml::Table = toml::from_str(&content)
+        .map_err(|e| format!("invalid config file {}: {}", config.display(), e))?;
 
-            let length = sequence.len();
-            if length < 50 {
-                continue;
+    let mut command = Args::command();
+    command.build();
+    let mut arguments = Vec::new();
+    for (key, value) in table {
+        let id = key.replace('-', "_");
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_id() == id.as_str() && id != "config")
+            .filter(|arg| arg.get_long().is_some())
+            .ok_or_else(|| format!("unknown option in {}: {}", config.display(), key))?;
+        if matches.value_source(&id) == Some(ValueSource::CommandLine) {
+            continue;
+        }
+        let long = format!("--{}", arg.get_long().expect("options have a long name"));
+
+        if !arg.get_action().takes_values() {
+            match value {
+                toml::Value::Boolean(true) => arguments.push(OsString::from(long)),
+                toml::Value::Boolean(false) => {}
+                _ => {
+                    return Err(format!(
+                        "{} expects true or false in {}",
+                        key,
+                        config.display()
+                    ))
+                }
             }
+            continue;
+        }
+        let values = match value {
+            toml::Value::Array(values) => values,
+            value => vec![value],
+        };
+        let values = values
+            .into_iter()
+            .map(|value| match value {
+                toml::Value::String(value) => Ok(value),
+                toml::Value::Integer(_) | toml::Value::Float(_) | toml::Value::Boolean(_) => {
+                    Ok(value.to_string())
+                }
+                _ => Err(format!("invalid value of {} in {}", key, config.display())),
+            })
+            .collect::<Result<Vec<String>, String>>()?;
+        // Values are given together to the options taking several at once, one by one otherwise
+        if arg
+            .get_num_args()
+            .is_some_and(|range| range.max_values() > 1)
+        {
+            arguments.push(OsString::from(long));
+            arguments.extend(values.into_iter().map(OsString::from));
+        } else {
+            arguments.extend(
+                values
+                    .into_iter()
+                    .map(|value| OsString::from(format!("{}={}", long, value))),
+            );
+        }
+    }
+    Ok(arguments)
+}
+
+/// Prints log messages to stderr at the level selected by `--quiet` and `--verbose`
+fn init_logger(args: &Args) {
+    let level = if args.quiet {
+        LevelFilter::Error
+    } else if args.verbose {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    };
+
+    env_logger::Builder::new()
+        .filter_level(level)
+        .format(|buf, record| match record.level() {
+            Level::Error => writeln!(buf, "Error: {}", record.args()),
+            Level::Warn => writeln!(buf, "Warning: {}", record.args()),
+            _ => writeln!(buf, "{}", record.args()),
+        })
+        .init();
+}
 
-            // Adjust the sequence based on its length
-            let adjusted_sequence = if length > processing_length {
-                if length < sequence_length_threshold {
-                    &sequence[..processing_length]
+/// Runs the analysis, returning the number of analyzed reads and whether all the samples
+/// passed the QC
+fn run(args: Args, arguments: &[OsString]) -> Result<(usize, bool), AppError> {
+    let started = SystemTime::now();
+    if args.random.is_some() {
+        info!(
+            "Random seed: {} (rerun with --seed to reproduce the sampling)",
+            args.seed.expect("the seed is drawn when not given")
+        );
+    }
+    if let Err(e) = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.threads)
+        .build_global()
+    {
+        warn!("failed to configure the thread pool: {}", e);
+    }
+
+    let inputs = input_files(&args)?;
+    let motif_options = MotifOptions {
+        kmer_lengths: args.kmer.clone(),
+        motifs: args
+            .motifs_file
+            .as_deref()
+            .map(|path| read_motifs_file(path, args.preserve_case, args.alphabet))
+            .transpose()?,
+        min_proportion: args.ratio / 100.0,
+        kmer_min_proportions: [(2, args.ratio_2), (3, args.ratio_3)]
+            .into_iter()
+            .filter_map(|(k, ratio)| Some((k, ratio? / 100.0)))
+            .collect(),
+        canonical: args.canonical,
+        both_strands: args.both_strands,
+        preserve_case: args.preserve_case,
+        ambiguous: args.ambiguous,
+        length_bins: (!args.length_bins.is_empty())
+            .then(|| LengthBins::new(args.length_bins.clone())),
+        gc_content: !args.no_gc,
+        non_overlapping: args.non_overlapping,
+        min_mean_quality: args.min_mean_qual,
+        phred_offset: args.phred_offset,
+        input_format: args.input_format,
+        trim_start: args.trim_start,
+        trim_end: args.trim_end,
+        dust_window: args.dust_window,
+        dust_threshold: args.dust_threshold,
+        homopolymers: args.homopolymers || args.max_homopolymer,
+        dinucleotide_composition: args.dinucleotide_composition,
+        enrichment: args.enrichment,
+        min_entropy: args.min_entropy,
+        min_read_length: args.min_read_length,
+        max_read_length: args.max_read_length.map(|length| length as usize),
+        long_reads: args.long_reads,
+        metric: args.metric,
+        weight: args.weight,
+        aggregate_stat: args.aggregate_stat,
+        name_filter: args.name_filter.clone(),
+        alphabet: args.alphabet,
+        interleaved: args.interleaved,
+        validate_quality_length: args.validate_quality_length,
+        by_tile: args.by_tile,
+        read_names: args.per_read_dust
+            || args.max_homopolymer
+            || args.emit_masked_fasta
+            || args.emit_bed,
+        dust_intervals: (args.emit_masked_fasta || args.emit_bed) && !args.use_external_sdust,
+        masked_composition: args.masked_composition,
+        max_memory: args.max_memory.map(|megabytes| (megabytes as usize) << 20),
+        checkpoint: args.checkpoint.clone().map(|path| CheckpointOptions {
+            path,
+            interval: Duration::from_secs(args.checkpoint_interval),
+            inputs: inputs
+                .iter()
+                .flat_map(|(input, input2)| std::iter::once(input).chain(input2))
+                .cloned()
+                .collect(),
+        }),
+        position_motifs: args
+            .position_profile
+            .iter()
+            .map(|motif| {
+                if args.preserve_case {
+                    motif.clone()
                 } else {
-                    &sequence
-                        [(sequence_length_threshold - processing_length)..sequence_length_threshold]
+                    motif.to_ascii_uppercase()
+                }
+            })
+            .collect(),
+    };
+
+    let output_dir = output_dir(&args, inputs.first().map(|(input, _)| input.as_str()))?;
+    if writes_to_stdout(&args) {
+        check_stdout_output(&args, inputs.len())?;
+    }
+    if args
+        .max_read_length
+        .is_some_and(|max_length| max_length < args.min_read_length as u64)
+    {
+        return Err(AppError::Input(format!(
+            "--max-read-length is below --min-read-length ({})",
+            args.min_read_length
+        )));
+    }
+    if args.sample_name.is_some() && inputs.len() > 1 && !args.aggregate {
+        return Err(AppError::Input(
+            "--sample-name names a single input, or the aggregated inputs".to_string(),
+        ));
+    }
+    if args.compare.is_some() && inputs.len() > 1 && !args.aggregate {
+        return Err(AppError::Input(
+            "--compare compares a single input, or the aggregated inputs, to another one"
+                .to_string(),
+        ));
+    }
+    if args.checkpoint.is_some() && inputs.len() > 1 {
+        return Err(AppError::Input(
+            "A checkpoint follows a single input, not several".to_string(),
+        ));
+    }
+    if args.per_mate && !args.interleaved && inputs.iter().all(|(_, input2)| input2.is_none()) {
+        warn!("--per-mate ignored without paired-end input");
+    }
+
+    // A single input, or the aggregated ones, make a single sample, of which the name fills the
+    // output directory in
+    let sample = if inputs.len() == 1 || args.aggregate {
+        Some(single_sample(&args, &inputs, &output_dir)?)
+    } else {
+        None
+    };
+    let output_dir = match &sample {
+        Some(sample) => fill_sample(&output_dir, sample),
+        None => output_dir,
+    };
+
+    let mut analyzed_reads = 0;
+    let mut qc_passed = true;
+    if let Some(sample) = sample {
+        if !writes_to_stdout(&args) {
+            create_output_dir(&output_dir)?;
+        }
+        let mut total_stats: Option<ReadStats> = None;
+        for (input, input2) in &inputs {
+            let read_stats = read_input(
+                &args,
+                &output_dir,
+                input,
+                input2.as_deref(),
+                &motif_options,
+                true,
+            )?;
+            analyzed_reads += read_stats.total_reads;
+            match total_stats.as_mut() {
+                Some(total_stats) => total_stats.merge(read_stats),
+                None => total_stats = Some(read_stats),
+            }
+        }
+        if let Some(total_stats) = total_stats {
+            qc_passed &= save_results(&args, &output_dir, &sample, &total_stats, &motif_options)?;
+
+            if let Some(other_input) = args.compare.as_deref().filter(|_| !writes_to_stdout(&args))
+            {
+                info!("Analyzing {} to compare with", other_input);
+                // The results of the sample are kept when the input to compare with is empty
+                let other_stats =
+                    read_input(&args, &output_dir, other_input, None, &motif_options, false)?;
+                analyzed_reads += other_stats.total_reads;
+                let output_comparison = output_dir.join("comparison.csv");
+                debug!(
+                    "Saving the comparison to CSV: {}",
+                    output_comparison.display()
+                );
+                save_comparison(
+                    &output_comparison,
+                    &compute_motif_stats(&total_stats, &motif_options),
+                    &compute_motif_stats(&other_stats, &motif_options),
+                    args.units,
+                )
+                .map_err(|e| AppError::Output(format!("Error saving the comparison: {}", e)))?;
+                info!("Comparison saved to '{}'", output_comparison.display());
+            }
+        }
+        // The checkpoint is only saved after a while, so a short run leaves none
+        if let Some(checkpoint) = &args.checkpoint {
+            match fs::remove_file(checkpoint) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    return Err(AppError::Output(format!(
+                        "Failed to remove checkpoint {}: {}",
+                        checkpoint.display(),
+                        e
+                    )))
                 }
+                _ => debug!("Checkpoint removed: {}", checkpoint.display()),
+            }
+        }
+    } else {
+        let mut sample_names: Vec<String> = Vec::new();
+        for (input, input2) in &inputs {
+            // Inputs sharing a name are told apart by their position
+            let mut sample_name = sample_name(input);
+            if sample_names.contains(&sample_name) {
+                sample_name = format!("{}_{}", sample_name, sample_names.len() + 1);
+            }
+            let sample_dir = if has_sample_placeholder(&output_dir) {
+                fill_sample(&output_dir, &sample_name)
             } else {
-                // Use full sequence if shorter than 150 bp
-                &sequence
+                output_dir.join(&sample_name)
             };
 
-            // Increment total_reads only for valid sequences
-            total_reads += 1;
+            create_output_dir(&sample_dir)?;
+            let read_stats = read_input(
+                &args,
+                &sample_dir,
+                input,
+                input2.as_deref(),
+                &motif_options,
+                true,
+            )?;
+            analyzed_reads += read_stats.total_reads;
+            qc_passed &= save_results(
+                &args,
+                &sample_dir,
+                &sample_name,
+                &read_stats,
+                &motif_options,
+            )?;
+            sample_names.push(sample_name);
+        }
+    }
+
+    if !writes_to_stdout(&args) {
+        // Next to the sample directories when their path is filled in with the sample names
+        let output_run_info = sample_template_root(&output_dir).join("run_info.json");
+        debug!("Saving run information: {}", output_run_info.display());
+        save_run_info(&output_run_info, &args, arguments, &inputs, started)?;
+    }
+
+    Ok((analyzed_reads, qc_passed))
+}
+
+/// Output directory standing for stdout, to which the results CSV is written alone
+const STDOUT_OUTPUT: &str = "-";
+
+/// Whether the results CSV is written to stdout instead of an output directory
+fn writes_to_stdout(args: &Args) -> bool {
+    args.output_dir.as_deref() == Some(STDOUT_OUTPUT)
+}
+
+/// Checks that the results of the run fit in a single CSV written to stdout, and warns about
+/// the requested files that are not saved then
+fn check_stdout_output(args: &Args, inputs: usize) -> Result<(), AppError> {
+    let unsupported = if args.format != OutputFormat::Csv {
+        Some("--format json or both")
+    } else if args.use_r_script {
+        Some("--use-r-script")
+    } else if args.emit_masked_fasta {
+        Some("--emit-masked-fasta")
+    } else if inputs > 1 && !args.aggregate {
+        Some("several inputs without --aggregate")
+    } else {
+        None
+    };
+    if let Some(unsupported) = unsupported {
+        return Err(AppError::Output(format!(
+            "Only the results CSV can be written to stdout, which rules out {}",
+            unsupported
+        )));
+    }
+
+    let ignored: Vec<&str> = [
+        (args.per_read_dust, "--per-read-dust"),
+        (args.homopolymers, "--homopolymers"),
+        (args.max_homopolymer, "--max-homopolymer"),
+        (args.dinucleotide_composition, "--dinucleotide-composition"),
+        (args.by_tile, "--by-tile"),
+        (args.multiqc, "--multiqc"),
+        (args.compare.is_some(), "--compare"),
+        (args.masked_composition, "--masked-composition"),
+        (!args.position_profile.is_empty(), "--position-profile"),
+    ]
+    .into_iter()
+    .filter_map(|(requested, option)| requested.then_some(option))
+    .collect();
+    if !ignored.is_empty() {
+        warn!(
+            "{} ignored when writing the results to stdout",
+            ignored.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Output directory given on the command line, or a directory of the current directory named
+/// after the first input, which must not exist yet unless forced
+fn output_dir(args: &Args, first_input: Option<&str>) -> Result<PathBuf, AppError> {
+    if let Some(dir) = &args.output_dir {
+        return Ok(PathBuf::from(dir));
+    }
+
+    let current_dir = env::current_dir()
+        .map_err(|e| AppError::Output(format!("Failed to get current directory: {}", e)))?;
+    if args.random_output_name {
+        return Ok(current_dir.join(format!("freq_motif_{}", Uuid::new_v4())));
+    }
+    let name = match &args.sample_name {
+        Some(sample) => sample.clone(),
+        None => first_input.map_or("reads".to_string(), sample_name),
+    };
+    let dir = current_dir.join(format!("freq_motif_{}", name));
+    if dir.exists() && !args.force {
+        return Err(AppError::Output(format!(
+            "Output directory {} already exists, use --force to write to it anyway",
+            dir.display()
+        )));
+    }
+    Ok(dir)
+}
+
+/// Placeholder of the output directory replaced by the name of the sample
+const SAMPLE_PLACEHOLDER: &str = "{sample}";
+
+/// Whether the output directory is filled in with the name of each sample
+fn has_sample_placeholder(output_dir: &Path) -> bool {
+    output_dir.to_string_lossy().contains(SAMPLE_PLACEHOLDER)
+}
+
+/// Output directory with its `{sample}` placeholders replaced by the name of a sample
+fn fill_sample(output_dir: &Path, sample: &str) -> PathBuf {
+    if !has_sample_placeholder(output_dir) {
+        return output_dir.to_path_buf();
+    }
+    PathBuf::from(
+        output_dir
+            .to_string_lossy()
+            .replace(SAMPLE_PLACEHOLDER, sample),
+    )
+}
+
+/// Part of the output directory before its first `{sample}` placeholder, holding the
+/// directories of all the samples, or the whole output directory without placeholder
+fn sample_template_root(output_dir: &Path) -> PathBuf {
+    let root: PathBuf = output_dir
+        .components()
+        .take_while(|component| {
+            !component
+                .as_os_str()
+                .to_string_lossy()
+                .contains(SAMPLE_PLACEHOLDER)
+        })
+        .collect();
+    if root.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        root
+    }
+}
+
+/// Name of the sample made by a single input, or by the aggregated inputs, which is named
+/// after the output directory unless its name is given
+fn single_sample(
+    args: &Args,
+    inputs: &[(String, Option<String>)],
+    output_dir: &Path,
+) -> Result<String, AppError> {
+    if let Some(sample) = &args.sample_name {
+        return Ok(sample.clone());
+    }
+    match inputs {
+        [(input, _)] => Ok(sample_name(input)),
+        _ if has_sample_placeholder(output_dir) => Err(AppError::Input(format!(
+            "Aggregated inputs need --sample-name to fill {} in the output directory",
+            SAMPLE_PLACEHOLDER
+        ))),
+        _ => Ok(output_dir
+            .file_name()
+            .map_or("aggregate".to_string(), |name| {
+                name.to_string_lossy().into_owned()
+            })),
+    }
+}
 
-            if total_reads % 10_000 == 0 {
-                eprintln!("Processed {} reads...", total_reads);
+/// Checks that a run could start and reports what is missing.
+/// Returns whether everything the run needs is ready.
+fn check(args: &Args) -> bool {
+    let mut ready = true;
+    let mut report = |what: &str, result: Result<(), String>, required: bool| match result {
+        Ok(()) => info!("{}: OK", what),
+        Err(e) if required => {
+            error!("{}: {}", what, e);
+            ready = false;
+        }
+        Err(e) => info!("{}: {} (not needed)", what, e),
+    };
+
+    let inputs = input_files(args);
+    match &inputs {
+        Ok(inputs) => {
+            for input in inputs
+                .iter()
+                .flat_map(|(input, input2)| std::iter::once(input).chain(input2))
+            {
+                let opened = open_fastq(input).map(|_| ()).map_err(|e| e.to_string());
+                report(&format!("Input {}", input), opened, true);
             }
+        }
+        Err(e) => report("Inputs", Err(e.to_string()), true),
+    }
 
-            let read_id = header.split_whitespace().next().unwrap_or("");
+    let first_input = inputs
+        .as_ref()
+        .ok()
+        .and_then(|inputs| inputs.first())
+        .map(|(input, _)| input.as_str());
+    match output_dir(args, first_input) {
+        Ok(dir) => {
+            // The directories of the samples are checked through the one holding them
+            let dir = sample_template_root(&dir);
+            let created = !dir.exists();
+            let test_file = dir.join(".freq-motif-check");
+            let writable = fs::create_dir_all(&dir)
+                .and_then(|_| fs::write(&test_file, b""))
+                .and_then(|_| fs::remove_file(&test_file))
+                .and_then(|_| {
+                    if created {
+                        fs::remove_dir(&dir)
+                    } else {
+                        Ok(())
+                    }
+                })
+                .map_err(|e| e.to_string());
+            report(
+                &format!("Output directory {}", dir.display()),
+                writable,
+                true,
+            );
+        }
+        Err(e) => report("Output directory", Err(e.to_string()), true),
+    }
 
-            // Write to FASTA for SDUST
-            writeln!(fasta_writer, ">{}", read_id).expect("Failed to write FASTA header");
-            writeln!(fasta_writer, "{}", adjusted_sequence)
-                .expect("Failed to write FASTA sequence");
-            read_lengths.insert(read_id.to_string(), length);
+    for (program, required) in [
+        ("sdust", args.use_external_sdust),
+        ("Rscript", args.use_r_script),
+        ("generate_barplot.R", args.use_r_script),
+    ] {
+        // Only whether the program can be started matters, not its exit status
+        let found = Command::new(program)
+            .arg("--version")
+            .output()
+            .map(|_| ())
+            .map_err(|e| format!("not found ({})", e));
+        report(&format!("Program {}", program), found, required);
+    }
 
-            let mut motif_frequencies: HashMap<String, u64> = HashMap::new();
+    if ready {
+        info!("Ready to run");
+    } else {
+        error!("Not ready to run");
+    }
+    ready
+}
 
-            // Update length based on adjusted sequence
-            let length = adjusted_sequence.len();
+/// Prints the supported input formats and compressions
+fn list_formats() {
+    println!("Input formats (--input-format):");
+    println!("  fastq  FASTQ, with sequences and qualities possibly wrapped");
+    println!("  fasta  FASTA, without qualities");
+    println!("  auto   FASTA if the first record starts with '>', FASTQ otherwise (default)");
+    println!("Compressions, detected from the first bytes of the input whatever its extension:");
+    println!("  gzip   1f 8b        .gz");
+    println!("  bzip2  42 5a 68     .bz2");
+    println!("  zstd   28 b5 2f fd  .zst");
+    println!("  none   plain text, also read as such under a compressed extension");
+}
 
-            for i in 0..length {
-                if i + 2 <= length {
-                    let dinucleotide = &adjusted_sequence[i..i + 2];
-                    *motif_frequencies
-                        .entry(dinucleotide.to_string())
-                        .or_insert(0) += 1;
-                }
+/// Prints the version of the tool and where the external programs are found, with their
+/// versions, for support requests
+fn doctor() {
+    println!("freq-motif-fastq {}", env!("CARGO_PKG_VERSION"));
+    println!("Built-in DUST and barplot renderer: available");
 
-                if i + 3 <= length {
-                    let trinucleotide = &adjusted_sequence[i..i + 3];
-                    *motif_frequencies
-                        .entry(trinucleotide.to_string())
-                        .or_insert(0) += 1;
-                }
+    for (program, version_args) in EXTERNAL_PROGRAMS {
+        match program_version(program, version_args) {
+            None => println!("{}: not found", program),
+            Some((path, Some(version))) => {
+                println!("{}: {} ({})", program, path.display(), version)
             }
+            Some((path, None)) => println!("{}: {}", program, path.display()),
+        }
+    }
+}
 
-            let total_dinucleotides = (length - 1) as f64;
-            let total_trinucleotides = (length - 2) as f64;
+/// External programs, with the arguments printing their version. SDUST has no version
+/// option, and the R script would run with one
+const EXTERNAL_PROGRAMS: [(&str, Option<&[&str]>); 4] = [
+    ("sdust", None),
+    ("Rscript", Some(&["--version"])),
+    (
+        "Rscript (ggplot2)",
+        Some(&[
+            "-e",
+            "cat('ggplot2', as.character(packageVersion('ggplot2')))",
+        ]),
+    ),
+    ("generate_barplot.R", None),
+];
 
-            for (motif, count) in motif_frequencies {
-                let proportion = if motif.len() == 2 {
-                    (count as f64) / total_dinucleotides
-                } else {
-                    (count as f64) / total_trinucleotides
-                };
+/// Where an external program is found, with the first line it prints when given its version
+/// arguments, if any. The program is the first word of `program`
+fn program_version(
+    program: &str,
+    version_args: Option<&[&str]>,
+) -> Option<(PathBuf, Option<String>)> {
+    let executable = program.split(' ').next().unwrap_or(program);
+    let path = find_program(executable)?;
+    let version = version_args.and_then(|version_args| {
+        let output = Command::new(&path).args(version_args).output().ok()?;
+        let text = [output.stdout, output.stderr].concat();
+        String::from_utf8_lossy(&text)
+            .lines()
+            .find(|line| !line.trim().is_empty())
+            .map(|line| line.trim().to_string())
+    });
+    Some((path, version))
+}
 
-                if proportion > min_proportion {
-                    *motif_counts.entry(motif).or_insert(0) += 1;
+/// Saves how a run was started, to tell later which settings produced its results: the
+/// command line, the value of every option, defaults included, the inputs with their size,
+/// and the versions of the tool and of the external programs it ran
+fn save_run_info(
+    path: &Path,
+    args: &Args,
+    arguments: &[OsString],
+    inputs: &[(String, Option<String>)],
+    started: SystemTime,
+) -> Result<(), AppError> {
+    let mut command = Args::command();
+    command.build();
+    let matches = command.clone().get_matches_from(arguments);
+    let mut parameters = serde_json::Map::new();
+    for arg in command.get_arguments() {
+        let id = arg.get_id().as_str();
+        let value = match arg.get_action() {
+            ArgAction::SetTrue => json!(matches.get_flag(id)),
+            action if action.takes_values() => {
+                let values: Vec<String> = matches
+                    .get_raw(id)
+                    .map(|values| {
+                        values
+                            .map(|value| value.to_string_lossy().into_owned())
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                let multiple = matches!(action, ArgAction::Append)
+                    || arg
+                        .get_num_args()
+                        .is_some_and(|range| range.max_values() > 1);
+                if multiple {
+                    json!(values)
+                } else {
+                    json!(values.into_iter().next())
                 }
             }
+            // Help and version
+            _ => continue,
+        };
+        parameters.insert(id.to_string(), value);
+    }
+    // The seed drawn when none is given is the one that reproduces the sampling
+    parameters.insert("seed".to_string(), json!(args.random.and(args.seed)));
+
+    let input_files: Vec<serde_json::Value> = inputs
+        .iter()
+        .flat_map(|(input, input2)| std::iter::once(input).chain(input2))
+        .map(|input| {
+            let size = (input != "-")
+                .then(|| fs::metadata(input).ok().map(|metadata| metadata.len()))
+                .flatten();
+            json!({ "path": input, "size": size })
+        })
+        .collect();
+
+    let mut programs = serde_json::Map::new();
+    for (program, version_args) in EXTERNAL_PROGRAMS {
+        let used = if program == "sdust" {
+            args.use_external_sdust
+        } else {
+            args.use_r_script
+        };
+        if used {
+            let found = program_version(program, version_args).map(
+                |(path, version)| json!({ "path": path.display().to_string(), "version": version }),
+            );
+            programs.insert(program.to_string(), json!(found));
+        }
+    }
+
+    let run_info = json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "command_line": env::args_os()
+            .map(|argument| argument.to_string_lossy().into_owned())
+            .collect::<Vec<String>>(),
+        "started": utc_timestamp(started),
+        "parameters": parameters,
+        "inputs": input_files,
+        "programs": programs,
+    });
+    AtomicFile::create(path)
+        .and_then(|mut file| {
+            serde_json::to_writer_pretty(&mut file, &run_info)?;
+            writeln!(file)?;
+            file.commit()
+        })
+        .map_err(|e| AppError::Output(format!("Error saving the run information: {}", e)))
+}
+
+/// Formats a time as an ISO 8601 UTC date and time, to the second
+fn utc_timestamp(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let (days, time_of_day) = (seconds / 86_400, seconds % 86_400);
+    // Civil date of a day count, shifted to start the years in March so that leap days end them
+    let shifted = days + 719_468;
+    let (era, day_of_era) = (shifted / 146_097, shifted % 146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time_of_day / 3_600,
+        time_of_day % 3_600 / 60,
+        time_of_day % 60
+    )
+}
+
+/// Finds an executable in the directories of `PATH`
+fn find_program(program: &str) -> Option<PathBuf> {
+    let path = env::var_os("PATH")?;
+    env::split_paths(&path)
+        .map(|dir| dir.join(program))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Lists the input files given with `--input`, `--input2` and `--input-list`,
+/// each with the file of its second mates, if any
+fn input_files(args: &Args) -> Result<Vec<(String, Option<String>)>, AppError> {
+    if !args.input2.is_empty() && args.input2.len() != args.input.len() {
+        return Err(AppError::Input(format!(
+            "{} files of second mates given for {} input files",
+            args.input2.len(),
+            args.input.len()
+        )));
+    }
+
+    let mut inputs: Vec<(String, Option<String>)> = args
+        .input
+        .iter()
+        .enumerate()
+        .map(|(i, input)| (input.clone(), args.input2.get(i).cloned()))
+        .collect();
+
+    if let Some(input_list) = &args.input_list {
+        let list = fs::read_to_string(input_list)
+            .map_err(|e| AppError::Input(format!("Failed to read {}: {}", input_list, e)))?;
+        for line in list.lines() {
+            let mut files = line.split_whitespace();
+            if let Some(input) = files.next() {
+                inputs.push((input.to_string(), files.next().map(str::to_string)));
+            }
         }
+    }
 
-        lines.next(); // Skip '+'
-        lines.next(); // Skip quality
+    if inputs.is_empty() {
+        return Err(AppError::Input(format!(
+            "No input file listed in {}",
+            args.input_list.as_deref().unwrap_or("")
+        )));
+    }
+    Ok(inputs)
+}
+
+/// Name of the subdirectory holding the results of an input, taken from its file name
+/// without the FASTQ and compression extensions
+fn sample_name(input: &str) -> String {
+    if input == "-" {
+        return "stdin".to_string();
+    }
+
+    let mut name = Path::new(input)
+        .file_name()
+        .map_or(input.to_string(), |name| {
+            name.to_string_lossy().into_owned()
+        });
+    for extension in [".gz", ".bz2", ".zst", ".fastq", ".fq"] {
+        if let Some(stripped) = name.strip_suffix(extension) {
+            name = stripped.to_string();
+        }
     }
+    name
+}
 
-    eprintln!("Total reads processed: {}", total_reads);
-    (motif_counts, total_reads, read_lengths)
+/// Creates the output directory if it doesn't exist
+fn create_output_dir(output_dir: &Path) -> Result<(), AppError> {
+    fs::create_dir_all(output_dir).map_err(|e| {
+        AppError::Output(format!(
+            "Failed to create output directory {}: {}",
+            output_dir.display(),
+            e
+        ))
+    })
 }
 
-/// Parses the SDUST output to calculate low-complexity reads
-fn parse_dust_output(
-    dust_data: &str,
-    read_lengths: &HashMap<String, usize>,
-    min_proportion: f64,
-) -> usize {
-    let mut low_complexity_reads = 0;
-    let mut masked_bases: HashMap<String, usize> = HashMap::new();
+/// Reads and counts the selected reads of an input, and of the file of its second mates.
+/// An empty input gets the results CSV of no read if `empty_results` is set.
+fn read_input(
+    args: &Args,
+    output_dir: &Path,
+    input_file: &str,
+    input2: Option<&str>,
+    motif_options: &MotifOptions,
+    empty_results: bool,
+) -> Result<ReadStats, AppError> {
+    let skip_reads = match args.skip_fraction {
+        Some(fraction) => skipped_fraction(args, input_file, fraction)?,
+        None => args.skip,
+    };
 
-    for line in dust_data.lines() {
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.len() == 3 {
-            let read_name = parts[0].to_string();
-            let start: usize = parts[1].parse().expect("Invalid start position");
-            let end: usize = parts[2].parse().expect("Invalid end position");
+    debug!("Opening the input file: {}", input_file);
+    let (mut reader, position) = open_fastq_with_position(input_file)
+        .map_err(|e| AppError::Input(format!("Failed to open {}: {}", input_file, e)))?;
+    let mut reader2 = input2
+        .map(|input2| {
+            debug!("Opening the second input file: {}", input2);
+            open_fastq(input2)
+                .map_err(|e| AppError::Input(format!("Failed to open {}: {}", input2, e)))
+        })
+        .transpose()?;
 
-            let length = end - start + 1;
-            *masked_bases.entry(read_name).or_insert(0) += length;
+    // An input left empty by a failed upstream step is told apart from one whose reads are all
+    // filtered out, and still gets its results CSV
+    let mut empty_input = None;
+    if is_empty_input(&mut reader, input_file)? {
+        empty_input = Some(input_file);
+    } else if let (Some(reader2), Some(input2)) = (reader2.as_mut(), input2) {
+        if is_empty_input(reader2, input2)? {
+            empty_input = Some(input2);
         }
     }
-    for (read_name, masked) in masked_bases {
-        if let Some(&total_length) = read_lengths.get(&read_name) {
-            let proportion = (masked as f64) / (total_length as f64);
-            if proportion > min_proportion {
-                low_complexity_reads += 1;
-            }
+    if let Some(empty_input) = empty_input {
+        if empty_results {
+            save_empty_results(args, output_dir, motif_options)?;
         }
+        return Err(AppError::EmptyInput(format!(
+            "The input contained no reads: {}",
+            empty_input
+        )));
+    }
+
+    let selection = match args.random {
+        Some(reads) => ReadSelection::Random {
+            reads,
+            seed: args.seed,
+        },
+        None => ReadSelection::First(match args.max_reads {
+            0 => usize::MAX,
+            max_reads => max_reads,
+        }),
+    };
+
+    // The bar is only drawn for interactive runs, logs being kept clean otherwise
+    let progress = if args.quiet || args.progress_interval == 0 {
+        Progress::Hidden
+    } else if io::stderr().is_terminal() {
+        Progress::bar(position)
+    } else {
+        Progress::Log(args.progress_interval, position)
+    };
+
+    debug!("Skipping the first {} reads...", skip_reads);
+    let count_reads = |fasta_writer: Option<&mut dyn Write>| {
+        process_reads_and_write_fasta(
+            reader,
+            reader2,
+            fasta_writer,
+            selection,
+            skip_reads,
+            &progress,
+            motif_options,
+        )
+    };
+    let reading_start = Instant::now();
+    let read_stats = if !args.use_external_sdust && (args.emit_masked_fasta || args.emit_bed) {
+        count_reads_with_dust_regions(args, output_dir, count_reads)?
+    } else if !args.use_external_sdust {
+        count_reads(None)?
+    } else if args.stream_sdust {
+        count_reads_with_streamed_sdust(args, count_reads)?
+    } else {
+        count_reads_with_sdust(args, output_dir, count_reads)?
+    };
+    debug!(
+        "Reads processed in {:.2} s",
+        reading_start.elapsed().as_secs_f64()
+    );
+
+    if read_stats.total_reads == 0 {
+        return Err(AppError::Input(format!(
+            "No read left to analyze in {} after skipping the first {} reads and filtering out short or invalid reads",
+            input_file, skip_reads
+        )));
+    }
+
+    Ok(read_stats)
+}
+
+/// Whether an input holds nothing once decompressed, checked without consuming it
+fn is_empty_input(reader: &mut Box<dyn BufRead>, input: &str) -> Result<bool, AppError> {
+    reader
+        .fill_buf()
+        .map(|buffer| buffer.is_empty())
+        .map_err(|e| AppError::Input(format!("Failed to read {}: {}", input, e)))
+}
+
+/// Saves the results CSV of no read, unless only the JSON is requested, for the pipelines
+/// expecting one for every sample
+fn save_empty_results(
+    args: &Args,
+    output_dir: &Path,
+    motif_options: &MotifOptions,
+) -> Result<(), AppError> {
+    if args.format == OutputFormat::Json {
+        return Ok(());
+    }
+    let stats = compute_motif_stats(&ReadStats::new(motif_options, false), motif_options);
+    if writes_to_stdout(args) {
+        let mut stdout = BufWriter::new(io::stdout().lock());
+        save_to_csv(&mut stdout, &stats, None, &csv_format(args))
+            .and_then(|_| stdout.flush())
+            .map_err(|e| AppError::Output(format!("Error writing the CSV to stdout: {}", e)))
+    } else {
+        let output_csv = output_dir.join("freq-motif.csv");
+        AtomicFile::create(&output_csv)
+            .and_then(|mut file| {
+                save_to_csv(&mut file, &stats, None, &csv_format(args))?;
+                file.commit()
+            })
+            .map_err(|e| AppError::Output(format!("Error saving the CSV file: {}", e)))
+    }
+}
+
+/// Layout of the results CSV file set by the options
+fn csv_format(args: &Args) -> CsvFormat {
+    CsvFormat {
+        delimiter: args.delimiter,
+        header: !args.no_header,
+        units: args.units,
+        sample: args.sample_name.clone(),
     }
+}
 
-    low_complexity_reads
+/// Number of reads, or read pairs, making up the given fraction of an input, counted in a
+/// first pass over it
+fn skipped_fraction(args: &Args, input_file: &str, fraction: f64) -> Result<usize, AppError> {
+    if input_file == "-" {
+        return Err(AppError::Input(
+            "--skip-fraction needs a file to count the reads of, not stdin".to_string(),
+        ));
+    }
+    debug!("Counting the reads of {}...", input_file);
+    let reads = open_fastq(input_file)
+        .map_err(|e| AppError::Input(format!("Failed to open {}: {}", input_file, e)))
+        .and_then(|reader| count_records(reader, args.input_format))?;
+    // Interleaved mates are skipped by pairs
+    let reads = if args.interleaved { reads / 2 } else { reads };
+    let skip_reads = (reads as f64 * fraction) as usize;
+    info!(
+        "Skipping {} of the {} reads of {} ({:.1}%)",
+        skip_reads,
+        reads,
+        input_file,
+        fraction * 100.0
+    );
+    Ok(skip_reads)
 }
 
-/// Saves the results to a CSV file
-fn save_to_csv(
-    output_file: &PathBuf,
-    data: &[(String, f64)],
-) -> Result<(), Box<dyn std::error::Error>> {
-    let mut file = File::create(output_file)?;
-    writeln!(file, "Motif,Proportion")?;
-    for (motif, proportion) in data {
-        writeln!(file, "{},{:.4}", motif, proportion)?;
+/// Arguments of `sdust` setting its window and threshold, followed by the extra ones given
+fn sdust_command(args: &Args) -> Command {
+    let mut command = Command::new("sdust");
+    command
+        .arg("-w")
+        .arg(args.dust_window.to_string())
+        .arg("-t")
+        .arg(args.dust_threshold.to_string());
+    if let Some(SdustArgs(sdust_args)) = &args.sdust_args {
+        command.args(sdust_args);
+    }
+    command
+}
+
+/// Creates the temporary FASTA file the reads are written to, in the system temporary
+/// directory, removed when dropped
+fn temporary_fasta() -> Result<tempfile::NamedTempFile, AppError> {
+    tempfile::Builder::new()
+        .prefix("freq-motif-")
+        .suffix(".fasta")
+        .tempfile()
+        .map_err(|e| AppError::Output(format!("Failed to create temporary FASTA file: {}", e)))
+}
+
+/// Counts the reads masked with the built-in DUST, then saves their masked regions to the
+/// output directory. With `--emit-masked-fasta`, the reads are written to a temporary FASTA
+/// file then saved with their masked regions lowercased.
+fn count_reads_with_dust_regions(
+    args: &Args,
+    output_dir: &Path,
+    count_reads: impl FnOnce(Option<&mut dyn Write>) -> Result<ReadStats, AppError>,
+) -> Result<ReadStats, AppError> {
+    let mut fasta_file = args.emit_masked_fasta.then(temporary_fasta).transpose()?;
+    let mut read_stats = match fasta_file.as_mut() {
+        Some(fasta_file) => count_reads(Some(&mut BufWriter::new(fasta_file.as_file_mut())))?,
+        None => count_reads(None)?,
+    };
+    let intervals = std::mem::take(&mut read_stats.dust_intervals);
+    save_dust_regions(
+        args,
+        output_dir,
+        fasta_file.as_ref().map(|fasta_file| fasta_file.path()),
+        &intervals,
+        &read_stats.read_names,
+    )?;
+    Ok(read_stats)
+}
+
+/// Saves the masked regions, and the masked reads of the temporary FASTA file, as requested
+fn save_dust_regions(
+    args: &Args,
+    output_dir: &Path,
+    fasta_file: Option<&Path>,
+    intervals: &[Vec<(usize, usize)>],
+    read_names: &[String],
+) -> Result<(), AppError> {
+    let extension = if args.compress_output { ".gz" } else { "" };
+    if args.emit_bed {
+        let output_bed = output_dir.join(format!("low_complexity.bed{}", extension));
+        debug!("Saving the masked regions to BED: {}", output_bed.display());
+        save_dust_bed(&output_bed, intervals, read_names)
+            .map_err(|e| AppError::Output(format!("Error saving the masked regions: {}", e)))?;
+    }
+    if let Some(fasta_file) = fasta_file {
+        let output_masked_fasta = output_dir.join(format!("masked.fasta{}", extension));
+        debug!(
+            "Saving the masked reads to FASTA: {}",
+            output_masked_fasta.display()
+        );
+        File::open(fasta_file)
+            .and_then(|fasta| {
+                save_masked_fasta(
+                    &output_masked_fasta,
+                    BufReader::new(fasta),
+                    intervals,
+                    read_names,
+                )
+            })
+            .map_err(|e| AppError::Output(format!("Error saving the masked reads: {}", e)))?;
     }
     Ok(())
 }
+
+/// Counts the reads, writing them to a temporary FASTA file then run through `sdust`.
+/// With `--emit-masked-fasta`, the reads of that file are saved to the output directory
+/// with their masked regions lowercased.
+fn count_reads_with_sdust(
+    args: &Args,
+    output_dir: &Path,
+    count_reads: impl FnOnce(Option<&mut dyn Write>) -> Result<ReadStats, AppError>,
+) -> Result<ReadStats, AppError> {
+    let mut fasta_file = temporary_fasta()?;
+    let mut read_stats = count_reads(Some(&mut BufWriter::new(fasta_file.as_file_mut())))?;
+    let fasta_file = fasta_file.into_temp_path();
+
+    debug!("Running SDUST...");
+    let sdust_start = Instant::now();
+    let status = sdust_command(args)
+        .arg(&fasta_file)
+        .output()
+        .map_err(|e| AppError::Sdust(format!("Failed to execute SDUST: {}", e)))?;
+
+    check_sdust_status(args, status.status)?;
+
+    let dust_output_data = String::from_utf8(status.stdout)
+        .map_err(|e| AppError::Sdust(format!("Failed to read SDUST output: {}", e)))?;
+    // Replace the masked bases of the built-in DUST, not run on this path
+    read_stats.masked_bases = parse_dust_output(&dust_output_data, read_stats.total_reads)?;
+    debug!("SDUST run in {:.2} s", sdust_start.elapsed().as_secs_f64());
+
+    let intervals = if args.emit_masked_fasta || args.emit_bed {
+        let intervals = parse_dust_intervals(&dust_output_data)?;
+        check_dust_intervals(&intervals, &read_stats.read_lengths)?;
+        intervals
+    } else {
+        Vec::new()
+    };
+    save_dust_regions(
+        args,
+        output_dir,
+        args.emit_masked_fasta.then_some(&*fasta_file),
+        &intervals,
+        &read_stats.read_names,
+    )?;
+
+    if args.keep_temp && args.compress_output {
+        let kept = PathBuf::from(format!("{}.gz", fasta_file.display()));
+        File::open(&fasta_file)
+            .and_then(|mut fasta| {
+                let mut compressed = OutputFile::create(&kept)?;
+                io::copy(&mut fasta, &mut compressed)?;
+                compressed.finish()
+            })
+            .map_err(|e| {
+                AppError::Output(format!("Failed to compress temporary FASTA file: {}", e))
+            })?;
+        info!("Temporary FASTA file kept: {}", kept.display());
+    } else if args.keep_temp {
+        let kept = fasta_file
+            .keep()
+            .map_err(|e| AppError::Output(format!("Failed to keep temporary FASTA file: {}", e)))?;
+        info!("Temporary FASTA file kept: {}", kept.display());
+    } else {
+        debug!("Cleaning up temporary files...");
+        fasta_file.close().map_err(|e| {
+            AppError::Output(format!("Failed to remove temporary FASTA file: {}", e))
+        })?;
+    }
+
+    Ok(read_stats)
+}
+
+/// Fails on an unsuccessful SDUST run, unless `--tolerate-sdust-failure` is set, in which case
+/// the reads missing from its output are left unmasked
+fn check_sdust_status(args: &Args, status: ExitStatus) -> Result<(), AppError> {
+    if status.success() {
+        Ok(())
+    } else if args.tolerate_sdust_failure {
+        warn!(
+            "SDUST execution failed ({}), using its output anyway",
+            status
+        );
+        Ok(())
+    } else {
+        Err(AppError::Sdust(format!(
+            "SDUST execution failed ({})",
+            status
+        )))
+    }
+}
+
+/// Counts the reads while piping them to `sdust`, whose output is parsed as it comes
+fn count_reads_with_streamed_sdust(
+    args: &Args,
+    count_reads: impl FnOnce(Option<&mut dyn Write>) -> Result<ReadStats, AppError>,
+) -> Result<ReadStats, AppError> {
+    debug!("Running SDUST on the reads as they are read...");
+    let mut child = sdust_command(args)
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| AppError::Sdust(format!("Failed to execute SDUST: {}", e)))?;
+
+    // SDUST output is read on its own thread so that neither pipe fills up
+    let stdout = child.stdout.take().expect("SDUST stdout is piped");
+    let parser = thread::spawn(move || read_dust_output(BufReader::new(stdout)));
+
+    // Closing stdin on drop lets SDUST finish
+    let stdin = child.stdin.take().expect("SDUST stdin is piped");
+    let counted = count_reads(Some(&mut BufWriter::new(stdin)));
+
+    let masked_bases = parser
+        .join()
+        .map_err(|_| AppError::Sdust("Failed to read SDUST output".to_string()))?;
+    let status = child
+        .wait()
+        .map_err(|e| AppError::Sdust(format!("Failed to execute SDUST: {}", e)))?;
+    let mut read_stats = counted?;
+    check_sdust_status(args, status)?;
+
+    let mut masked_bases = masked_bases?;
+    if masked_bases.len() > read_stats.total_reads {
+        return Err(AppError::Sdust(format!(
+            "Unknown read in SDUST output: {}",
+            masked_bases.len() - 1
+        )));
+    }
+    masked_bases.resize(read_stats.total_reads, 0);
+    // Replace the masked bases of the built-in DUST, not run on this path
+    read_stats.masked_bases = masked_bases;
+
+    Ok(read_stats)
+}
+
+/// Categories of reads reported alongside the motifs
+const CATEGORIES: [&str; 3] = [LOW_COMPLEXITY_MOTIF, AMBIGUOUS_MOTIF, LOW_ENTROPY_MOTIF];
+
+/// Whether a motif is drawn on the barplot: the categories always are, and the motifs when
+/// their length is plotted
+fn is_plotted(args: &Args, motif: &str) -> bool {
+    CATEGORIES.contains(&motif)
+        || args.plot_kmer.is_empty()
+        || args.plot_kmer.contains(&motif.chars().count())
+}
+
+/// Number of bases in kb, Mb or Gb, whichever keeps it at or above 1
+fn human_readable_yield(bases: u64) -> String {
+    let bases = bases as f64;
+    if bases >= 1e9 {
+        format!("{:.2} Gb", bases / 1e9)
+    } else if bases >= 1e6 {
+        format!("{:.2} Mb", bases / 1e6)
+    } else {
+        format!("{:.2} kb", bases / 1e3)
+    }
+}
+
+/// Failed QC gates of a sample, as a description of each
+fn qc_failures(args: &Args, stats: &MotifStats) -> Vec<String> {
+    let mut failures = Vec::new();
+    if let Some(max) = args.fail_if_low_complexity {
+        if stats.low_complexity_proportion > max {
+            failures.push(format!(
+                "{} at {:.2}% (above {}%)",
+                LOW_COMPLEXITY_MOTIF, stats.low_complexity_proportion, max
+            ));
+        }
+    }
+    if let Some(max) = args.fail_if_motif {
+        failures.extend(
+            stats
+                .proportions
+                .iter()
+                .filter(|(motif, proportion)| {
+                    !CATEGORIES.contains(&motif.as_str()) && *proportion > max
+                })
+                .map(|(motif, proportion)| {
+                    format!("{} at {:.2}% (above {}%)", motif, proportion, max)
+                }),
+        );
+    }
+    failures
+}
+
+/// Computes the statistics of the counted reads and saves them to the output directory,
+/// returning whether the sample passed the QC gates
+fn save_results(
+    args: &Args,
+    output_dir: &Path,
+    sample: &str,
+    read_stats: &ReadStats,
+    motif_options: &MotifOptions,
+) -> Result<bool, AppError> {
+    let output_csv = output_dir.join("freq-motif.csv");
+    let output_json = output_dir.join("freq-motif.json");
+    let output_multiqc = output_dir.join(format!("{}_freq_motif_mqc.tsv", sample));
+    let output_graph = output_dir.join("barplot_freq-motif.png");
+    let output_gc = output_dir.join("gc_content.csv");
+    let output_bases = output_dir.join("base_composition.csv");
+    let output_max_motif = output_dir.join("max_motif_proportion_hist.csv");
+    let output_masked_composition = output_dir.join("masked_composition.csv");
+    let output_lengths = output_dir.join("read_lengths.csv");
+    let output_length_hist = output_dir.join("read_length_histogram.csv");
+    let output_flagged = output_dir.join("flagged_motifs.txt");
+    let output_profile = output_dir.join("position_profile.csv");
+    let output_masked = output_dir.join("low_complexity_distribution.csv");
+    let output_homopolymers = output_dir.join("homopolymer_stats.csv");
+    let output_dinucleotides = output_dir.join("dinucleotide_composition.csv");
+    let output_tiles = output_dir.join("tile_stats.csv");
+    let output_entropy = output_dir.join("entropy_histogram.csv");
+    // Per-read outputs are the large ones, gzipped with --compress-output
+    let per_read_extension = if args.compress_output { ".gz" } else { "" };
+    let output_longest_homopolymers =
+        output_dir.join(format!("max_homopolymer.csv{}", per_read_extension));
+    let output_per_read_dust = output_dir.join(format!("per_read_dust.tsv{}", per_read_extension));
+
+    debug!("Sorting results...");
+    let mut stats = compute_motif_stats(read_stats, motif_options);
+    // Motifs are flagged and gated among all of them, before only the top ones are kept
+    let flagged = stats.flagged_motifs(args.ratio);
+    let qc_failures = qc_failures(args, &stats);
+    if let Some(top) = args.top {
+        stats.keep_top_motifs(top);
+    }
+    let mate_proportions = stats.mate_proportions.as_ref().filter(|_| args.per_mate);
+
+    // The R script draws the barplot from the CSV, which is then removed afterwards if not requested
+    let temporary_csv = args.format == OutputFormat::Json && args.use_r_script;
+    let csv_format = csv_format(args);
+    if writes_to_stdout(args) {
+        debug!("Writing results to stdout");
+        let mut stdout = BufWriter::new(io::stdout().lock());
+        save_to_csv(&mut stdout, &stats, mate_proportions, &csv_format)
+            .and_then(|_| stdout.flush())
+            .map_err(|e| AppError::Output(format!("Error writing the CSV to stdout: {}", e)))?;
+    } else if args.format != OutputFormat::Json || temporary_csv {
+        debug!("Saving results to CSV: {}", output_csv.display());
+        AtomicFile::create(&output_csv)
+            .and_then(|mut file| {
+                save_to_csv(&mut file, &stats, mate_proportions, &csv_format)?;
+                file.commit()
+            })
+            .map_err(|e| AppError::Output(format!("Error saving the CSV file: {}", e)))?;
+    }
+
+    let length_summary = &stats.read_lengths;
+    info!(
+        "Sequenced yield: {} bp ({})",
+        length_summary.total_bases,
+        human_readable_yield(length_summary.total_bases)
+    );
+    info!(
+        "Read lengths: min {}, max {}, mean {:.1}, median {:.1}, N50 {}",
+        length_summary.min,
+        length_summary.max,
+        length_summary.mean,
+        length_summary.median,
+        length_summary.n50
+    );
+    if writes_to_stdout(args) {
+        info!("Analysis completed successfully. Results written to stdout.");
+        if !qc_failures.is_empty() {
+            warn!("QC failed: {}", qc_failures.join(", "));
+        }
+        return Ok(qc_failures.is_empty());
+    }
+    debug!(
+        "Saving read-length statistics to CSV: {} and {}",
+        output_lengths.display(),
+        output_length_hist.display()
+    );
+    save_length_summary(&output_lengths, length_summary)
+        .and_then(|_| {
+            save_length_histogram(
+                &output_length_hist,
+                &LengthBins::new(args.length_hist_bins.clone()),
+                &read_stats.read_lengths,
+            )
+        })
+        .map_err(|e| AppError::Output(format!("Error saving the read-length files: {}", e)))?;
+
+    debug!(
+        "Saving the distribution of masked fractions to CSV: {}",
+        output_masked.display()
+    );
+    save_low_complexity_distribution(
+        &output_masked,
+        &read_stats.masked_bases,
+        &read_stats.read_lengths,
+    )
+    .map_err(|e| {
+        AppError::Output(format!(
+            "Error saving the low-complexity distribution: {}",
+            e
+        ))
+    })?;
+
+    if args.per_read_dust {
+        debug!(
+            "Saving the masked bases of each read to TSV: {}",
+            output_per_read_dust.display()
+        );
+        save_per_read_dust(
+            &output_per_read_dust,
+            &read_stats.masked_bases,
+            &read_stats.read_lengths,
+            &read_stats.read_names,
+        )
+        .map_err(|e| {
+            AppError::Output(format!("Error saving the masked bases of the reads: {}", e))
+        })?;
+    }
+
+    let flagged_names: Vec<&str> = flagged.iter().map(|(motif, _)| motif.as_str()).collect();
+    info!(
+        "Motifs in more than {}% of the reads: {}",
+        args.ratio,
+        if flagged.is_empty() {
+            "none".to_string()
+        } else {
+            flagged_names.join(", ")
+        }
+    );
+    debug!("Saving flagged motifs: {}", output_flagged.display());
+    save_flagged_motifs(&output_flagged, &flagged)
+        .map_err(|e| AppError::Output(format!("Error saving the flagged motifs: {}", e)))?;
+
+    if let Some(mean_quality) = stats.mean_quality {
+        info!("Mean read quality: {:.1}", mean_quality);
+    }
+
+    info!("N content: {:.2}%", read_stats.base_composition.n_content());
+    debug!("Saving base composition to CSV: {}", output_bases.display());
+    save_base_composition(&output_bases, &read_stats.base_composition)
+        .map_err(|e| AppError::Output(format!("Error saving the base composition: {}", e)))?;
+
+    debug!(
+        "Saving the histogram of the most frequent motif of the reads to CSV: {}",
+        output_max_motif.display()
+    );
+    save_max_motif_histogram(&output_max_motif, &read_stats.max_motif_stats).map_err(|e| {
+        AppError::Output(format!(
+            "Error saving the histogram of the most frequent motifs: {}",
+            e
+        ))
+    })?;
+
+    if let Some(gc_stats) = &read_stats.gc_stats {
+        info!("Overall GC content: {:.2}%", gc_stats.overall_gc());
+        debug!(
+            "Saving GC content histogram to CSV: {}",
+            output_gc.display()
+        );
+        save_gc_histogram(&output_gc, gc_stats)
+            .map_err(|e| AppError::Output(format!("Error saving the GC content file: {}", e)))?;
+    }
+
+    if let Some(entropy_stats) = &read_stats.entropy_stats {
+        info!(
+            "Reads with a base entropy below {} bits: {:.2}%",
+            entropy_stats.min_entropy,
+            entropy_stats.low_entropy_proportion()
+        );
+        debug!(
+            "Saving entropy histogram to CSV: {}",
+            output_entropy.display()
+        );
+        save_entropy_histogram(&output_entropy, entropy_stats)
+            .map_err(|e| AppError::Output(format!("Error saving the entropy histogram: {}", e)))?;
+    }
+
+    if let Some(homopolymer_stats) = &read_stats.homopolymer_stats {
+        if args.homopolymers {
+            debug!(
+                "Saving homopolymer statistics to CSV: {}",
+                output_homopolymers.display()
+            );
+            save_homopolymer_stats(&output_homopolymers, homopolymer_stats).map_err(|e| {
+                AppError::Output(format!("Error saving the homopolymer statistics: {}", e))
+            })?;
+        }
+        if args.max_homopolymer {
+            debug!(
+                "Saving the longest homopolymer of each read to CSV: {}",
+                output_longest_homopolymers.display()
+            );
+            save_longest_homopolymers(
+                &output_longest_homopolymers,
+                homopolymer_stats,
+                &read_stats.read_names,
+            )
+            .map_err(|e| {
+                AppError::Output(format!("Error saving the longest homopolymers: {}", e))
+            })?;
+        }
+    }
+
+    if let Some(dinucleotide_stats) = &read_stats.dinucleotide_stats {
+        debug!(
+            "Saving the dinucleotide composition to CSV: {}",
+            output_dinucleotides.display()
+        );
+        save_dinucleotide_composition(&output_dinucleotides, dinucleotide_stats).map_err(|e| {
+            AppError::Output(format!("Error saving the dinucleotide composition: {}", e))
+        })?;
+    }
+
+    if let Some(masked_composition) = &read_stats.masked_composition {
+        debug!(
+            "Saving the composition of the masked regions to CSV: {}",
+            output_masked_composition.display()
+        );
+        save_masked_composition(
+            &output_masked_composition,
+            masked_composition,
+            motif_options,
+        )
+        .map_err(|e| {
+            AppError::Output(format!(
+                "Error saving the composition of the masked regions: {}",
+                e
+            ))
+        })?;
+    }
+
+    if args.by_tile {
+        debug!("Saving tile statistics to CSV: {}", output_tiles.display());
+        save_tile_stats(&output_tiles, &stats.tiles, args.units)
+            .map_err(|e| AppError::Output(format!("Error saving the tile statistics: {}", e)))?;
+    }
+
+    if !motif_options.position_motifs.is_empty() {
+        debug!(
+            "Saving position profile to CSV: {}",
+            output_profile.display()
+        );
+        save_position_profile(&output_profile, &stats.position_profile)
+            .map_err(|e| AppError::Output(format!("Error saving the position profile: {}", e)))?;
+    }
+
+    if args.format != OutputFormat::Csv {
+        debug!("Saving results to JSON: {}", output_json.display());
+        let metadata = JsonMetadata {
+            sample: sample.to_string(),
+            total_reads: stats.total_reads,
+            max_reads: args.max_reads,
+            ratio: args.ratio,
+            units: args.units,
+            weight: args.weight,
+            low_complexity_proportion: args.units.convert(stats.low_complexity_proportion),
+            gc_content: stats.gc_content,
+            mean_quality: stats.mean_quality,
+            read_lengths: stats.read_lengths.clone(),
+            min_read_length: args.min_read_length,
+            short_reads: read_stats.short_reads,
+            flagged_motifs: flagged.iter().map(|(motif, _)| motif.clone()).collect(),
+            low_complexity_flagged: flagged
+                .iter()
+                .any(|(motif, _)| motif == LOW_COMPLEXITY_MOTIF),
+            seed: args.random.and(args.seed),
+        };
+        save_to_json(
+            &output_json,
+            &metadata,
+            &stats.proportions,
+            mate_proportions,
+            stats.enrichment.as_ref(),
+        )
+        .map_err(|e| AppError::Output(format!("Error saving the JSON file: {}", e)))?;
+    }
+
+    if args.multiqc {
+        debug!("Saving MultiQC table: {}", output_multiqc.display());
+        save_multiqc(&output_multiqc, sample, &stats)
+            .map_err(|e| AppError::Output(format!("Error saving the MultiQC table: {}", e)))?;
+    }
+
+    let plotting_start = Instant::now();
+    if args.no_plot {
+        debug!("Barplot not drawn (--no-plot)");
+    } else if args.use_r_script {
+        debug!("Running R script for barplot...");
+        let mut command = Command::new("generate_barplot.R");
+        command
+            .arg(output_csv.as_os_str())
+            .arg(output_graph.as_os_str())
+            .arg(args.ratio.to_string()); // Pass the -r parameter
+        if !args.plot_kmer.is_empty() {
+            let lengths: Vec<String> = args.plot_kmer.iter().map(usize::to_string).collect();
+            command.arg(lengths.join(","));
+        }
+        let status = command
+            .status()
+            .map_err(|e| AppError::Plot(format!("Failed to run Rscript: {}", e)))?;
+
+        if !status.success() {
+            return Err(AppError::Plot("Rscript execution failed".to_string()));
+        }
+    } else {
+        debug!("Drawing the barplot: {}", output_graph.display());
+        let plotted: Vec<(String, f64)> = stats
+            .proportions
+            .iter()
+            .filter(|(motif, _)| is_plotted(args, motif))
+            .cloned()
+            .collect();
+        plot::draw_barplot(&output_graph, &plotted, args.ratio)?;
+    }
+    if !args.no_plot {
+        debug!(
+            "Barplot drawn in {:.2} s",
+            plotting_start.elapsed().as_secs_f64()
+        );
+    }
+
+    let mut saved_files = Vec::new();
+    match args.format {
+        OutputFormat::Csv => saved_files.push(&output_csv),
+        OutputFormat::Json => {
+            if temporary_csv {
+                fs::remove_file(&output_csv).map_err(|e| {
+                    AppError::Output(format!("Failed to remove temporary CSV file: {}", e))
+                })?;
+            }
+            saved_files.push(&output_json);
+        }
+        OutputFormat::Both => saved_files.extend([&output_csv, &output_json]),
+    }
+    if args.multiqc {
+        saved_files.push(&output_multiqc);
+    }
+    saved_files.extend([
+        &output_lengths,
+        &output_length_hist,
+        &output_masked,
+        &output_flagged,
+        &output_bases,
+        &output_max_motif,
+    ]);
+    if args.per_read_dust {
+        saved_files.push(&output_per_read_dust);
+    }
+    if read_stats.gc_stats.is_some() {
+        saved_files.push(&output_gc);
+    }
+    if read_stats.entropy_stats.is_some() {
+        saved_files.push(&output_entropy);
+    }
+    if args.homopolymers {
+        saved_files.push(&output_homopolymers);
+    }
+    if args.max_homopolymer {
+        saved_files.push(&output_longest_homopolymers);
+    }
+    if read_stats.dinucleotide_stats.is_some() {
+        saved_files.push(&output_dinucleotides);
+    }
+    if read_stats.masked_composition.is_some() {
+        saved_files.push(&output_masked_composition);
+    }
+    if args.by_tile {
+        saved_files.push(&output_tiles);
+    }
+    if !motif_options.position_motifs.is_empty() {
+        saved_files.push(&output_profile);
+    }
+    if !args.no_plot {
+        saved_files.push(&output_graph);
+    }
+
+    let saved_files: Vec<String> = saved_files
+        .iter()
+        .map(|path| format!("'{}'", path.display()))
+        .collect();
+    info!(
+        "Analysis completed successfully. Results saved to {}.",
+        saved_files.join(", ")
+    );
+
+    if !qc_failures.is_empty() {
+        warn!(
+            "QC failed for {}: {}",
+            output_dir.display(),
+            qc_failures.join(", ")
+        );
+    }
+    Ok(qc_failures.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kmer_lengths_are_bounded() {
+        assert!(parse_kmer_length("0").is_err());
+        assert_eq!(parse_kmer_length("1"), Ok(1));
+        assert_eq!(
+            parse_kmer_length(&MAX_KMER_LENGTH.to_string()),
+            Ok(MAX_KMER_LENGTH)
+        );
+        assert!(parse_kmer_length(&(MAX_KMER_LENGTH + 1).to_string()).is_err());
+        assert!(Args::try_parse_from(["freq-motif-fastq", "-i", "x.fq", "-k", "2,16"]).is_err());
+    }
+
+    #[test]
+    fn command_line_overrides_the_config_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("config.toml");
+        fs::write(
+            &config,
+            "input = \"x.fq\"\nratio = 20\nkmer = [2, 3]\nno-gc = true\nmin-read-length = 30\n",
+        )
+        .unwrap();
+        let command_line: Vec<OsString> = vec![
+            "freq-motif-fastq".into(),
+            "--ratio".into(),
+            "30".into(),
+            "--min-read-length=40".into(),
+            "--config".into(),
+            config.clone().into(),
+        ];
+        let arguments = with_config_arguments(command_line).unwrap();
+        let args = Args::try_parse_from(&arguments).unwrap();
+        assert_eq!(args.input, ["x.fq"]);
+        assert_eq!(args.ratio, 30.0);
+        assert_eq!(args.min_read_length, 40);
+        assert_eq!(args.kmer, [2, 3]);
+        assert!(args.no_gc);
+
+        let matches = Args::command()
+            .ignore_errors(true)
+            .get_matches_from(["freq-motif-fastq"]);
+        fs::write(&config, "no-gc = 1\n").unwrap();
+        assert!(config_arguments(&config, &matches).is_err());
+        fs::write(&config, "no-such-option = true\n").unwrap();
+        assert!(config_arguments(&config, &matches).is_err());
+        fs::write(&config, "no-gc = false\n").unwrap();
+        assert_eq!(config_arguments(&config, &matches), Ok(Vec::new()));
+    }
+
+    /// Arguments of a run of `input` to `output_dir`, analyzing every read of 10 bases or more,
+    /// with extra options, and the command line they are parsed from
+    fn run_args(input: &Path, output_dir: &Path, options: &[&str]) -> (Args, Vec<OsString>) {
+        let mut arguments: Vec<OsString> = vec![
+            "freq-motif-fastq".into(),
+            "-i".into(),
+            input.into(),
+            "-o".into(),
+            output_dir.into(),
+            "--skip=0".into(),
+            "--min-read-length=10".into(),
+            "--no-plot".into(),
+            "--quiet".into(),
+        ];
+        arguments.extend(options.iter().map(OsString::from));
+        (Args::try_parse_from(&arguments).unwrap(), arguments)
+    }
+
+    /// An AT repeat and three reads of Ns, bucketed as ambiguous
+    const AMBIGUOUS_FASTQ: &str = "@repeat\nATATATATATAT\n+\nIIIIIIIIIIII\n\
+                                   @n1\nNNNNNNNNNNNN\n+\nIIIIIIIIIIII\n\
+                                   @n2\nNNNNNNNNNNNN\n+\nIIIIIIIIIIII\n\
+                                   @n3\nNNNNNNNNNNNN\n+\nIIIIIIIIIIII\n";
+
+    #[test]
+    fn motif_qc_gate_leaves_out_the_categories() {
+        let dir = tempfile::tempdir().unwrap();
+        let stats_with = |options: &[&str]| {
+            let (args, _) = run_args(Path::new("x.fq"), dir.path(), options);
+            let motif_options = MotifOptions {
+                kmer_lengths: args.kmer.clone(),
+                min_read_length: args.min_read_length,
+                ambiguous: args.ambiguous,
+                ..MotifOptions::default()
+            };
+            let read_stats = process_reads_and_write_fasta(
+                AMBIGUOUS_FASTQ.as_bytes(),
+                None,
+                None,
+                ReadSelection::First(usize::MAX),
+                0,
+                &Progress::Hidden,
+                &motif_options,
+            )
+            .unwrap();
+            (args, compute_motif_stats(&read_stats, &motif_options))
+        };
+
+        // Ambiguous makes up 75% of the reads, the AT repeat 25%
+        let (args, stats) = stats_with(&["-k", "2", "--ambiguous=bucket", "--fail-if-motif=30"]);
+        assert!(stats
+            .proportions
+            .iter()
+            .any(|(motif, proportion)| motif == AMBIGUOUS_MOTIF && *proportion > 30.0));
+        assert!(qc_failures(&args, &stats).is_empty());
+
+        let (args, stats) = stats_with(&["-k", "2", "--ambiguous=bucket", "--fail-if-motif=20"]);
+        assert_eq!(
+            qc_failures(&args, &stats),
+            ["AT at 25.00% (above 20%)", "TA at 25.00% (above 20%)"]
+        );
+    }
+
+    #[test]
+    fn failed_qc_exits_with_the_qc_code() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("a.fastq");
+        fs::write(&input, AMBIGUOUS_FASTQ).unwrap();
+        let output_dir = dir.path().join("out");
+
+        let (args, arguments) = run_args(&input, &output_dir, &["-k", "2", "--fail-if-motif=20"]);
+        let qc_fail_code = args.qc_fail_code;
+        assert_eq!(exit_code(&run(args, &arguments), qc_fail_code), 10);
+        let (args, arguments) = run_args(&input, &output_dir, &["-k", "2", "--fail-if-motif=60"]);
+        assert_eq!(exit_code(&run(args, &arguments), qc_fail_code), 0);
+        assert_eq!(exit_code(&Ok((1, false)), 3), 3);
+    }
+
+    #[test]
+    fn empty_comparison_input_keeps_the_results() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("a.fastq");
+        let empty = dir.path().join("empty.fastq");
+        fs::write(&input, "@a\nATATATATATAT\n+\nIIIIIIIIIIII\n").unwrap();
+        fs::write(&empty, "").unwrap();
+        let output_dir = dir.path().join("out");
+        let (args, arguments) =
+            run_args(&input, &output_dir, &["--compare", empty.to_str().unwrap()]);
+
+        let result = run(args, &arguments);
+        assert_eq!(result.map_err(|e| e.exit_code()).err(), Some(7));
+        let csv = fs::read_to_string(output_dir.join("freq-motif.csv")).unwrap();
+        assert!(
+            csv.lines().any(|line| line.starts_with("AT,100.0000")),
+            "{}",
+            csv
+        );
+    }
+}
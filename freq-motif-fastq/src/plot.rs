@@ -0,0 +1,154 @@
+//! Native rendering of the motif barplot, matching the one drawn by `generate_barplot.R`.
+
+use crate::{AppError, LOW_COMPLEXITY_MOTIF};
+use plotters::prelude::*;
+use plotters::style::text_anchor::{HPos, Pos, VPos};
+use std::error::Error;
+use std::path::Path;
+
+/// Size of the PNG image, in pixels
+const IMAGE_SIZE: (u32, u32) = (1200, 800);
+/// Upper bound of the Y axis; taller bars are cut and labelled with their value
+const Y_MAX: f64 = 50.0;
+/// Motifs at or below this proportion are left out of the plot
+const MIN_PLOTTED_PROPORTION: f64 = 1.0;
+
+const RED: RGBColor = RGBColor(255, 0, 0);
+const ORANGE: RGBColor = RGBColor(255, 165, 0);
+const GREEN: RGBColor = RGBColor(0, 255, 0);
+const LIGHT_BLUE: RGBColor = RGBColor(173, 216, 230);
+const GRAY_90: RGBColor = RGBColor(229, 229, 229);
+const GRAY_95: RGBColor = RGBColor(242, 242, 242);
+
+/// Colour of a bar: LowComplexity is graded by its value, and dinucleotides other than
+/// homopolymers standing between 5% and the LowComplexity proportion are highlighted
+fn bar_color(motif: &str, proportion: f64, low_complexity: f64) -> RGBColor {
+    if motif == LOW_COMPLEXITY_MOTIF {
+        if proportion > 15.0 {
+            RED
+        } else if proportion > 5.0 {
+            ORANGE
+        } else {
+            GREEN
+        }
+    } else if motif.len() == 2 {
+        let homopolymer = matches!(motif, "AA" | "TT" | "CC" | "GG");
+        if proportion < low_complexity && proportion > 5.0 && !homopolymer {
+            RED
+        } else {
+            BLUE
+        }
+    } else {
+        LIGHT_BLUE
+    }
+}
+
+/// Draws the barplot of the sorted proportions to a PNG file, with the `--ratio` threshold
+/// as a dashed line
+pub fn draw_barplot(
+    output_file: &Path,
+    proportions: &[(String, f64)],
+    ratio: f64,
+) -> Result<(), AppError> {
+    draw(output_file, proportions, ratio)
+        .map_err(|e| AppError::Plot(format!("Failed to draw the barplot: {}", e)))
+}
+
+fn draw(
+    output_file: &Path,
+    proportions: &[(String, f64)],
+    ratio: f64,
+) -> Result<(), Box<dyn Error>> {
+    let low_complexity = proportions
+        .iter()
+        .find(|(motif, _)| motif == LOW_COMPLEXITY_MOTIF)
+        .map_or(0.0, |&(_, proportion)| proportion);
+    let bars: Vec<(&str, f64, RGBColor)> = proportions
+        .iter()
+        .filter(|(_, proportion)| *proportion > MIN_PLOTTED_PROPORTION)
+        .map(|(motif, proportion)| {
+            let color = bar_color(motif, *proportion, low_complexity);
+            (motif.as_str(), *proportion, color)
+        })
+        .collect();
+
+    let root = BitMapBackend::new(output_file, IMAGE_SIZE).into_drawing_area();
+    root.fill(&WHITE)?;
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption(
+            format!(
+                "Proportion of reads with at least {:.0}% of the specified motif",
+                ratio
+            ),
+            ("sans-serif", 28),
+        )
+        .margin(20)
+        .x_label_area_size(130)
+        .y_label_area_size(70)
+        // A segmented range has one more segment than its length, to hold one bar per segment
+        .build_cartesian_2d(
+            (0..bars.len().saturating_sub(1)).into_segmented(),
+            0.0..Y_MAX,
+        )?;
+
+    let motif_label = |value: &SegmentValue<usize>| match value {
+        SegmentValue::CenterOf(i) => bars.get(*i).map_or(String::new(), |bar| bar.0.to_string()),
+        _ => String::new(),
+    };
+    chart
+        .configure_mesh()
+        .disable_x_mesh()
+        .bold_line_style(GRAY_90)
+        .light_line_style(GRAY_95)
+        .y_max_light_lines(1)
+        .x_labels(bars.len())
+        .x_label_formatter(&motif_label)
+        .x_label_style(
+            ("sans-serif", 16)
+                .into_font()
+                .transform(FontTransform::Rotate270),
+        )
+        .y_label_style(("sans-serif", 16))
+        .x_desc("Motif")
+        .y_desc("Proportion (%)")
+        .axis_desc_style(("sans-serif", 20))
+        .draw()?;
+
+    chart.draw_series(bars.iter().enumerate().map(|(i, &(_, proportion, color))| {
+        let mut bar = Rectangle::new(
+            [
+                (SegmentValue::Exact(i), 0.0),
+                (SegmentValue::Exact(i + 1), proportion.min(Y_MAX)),
+            ],
+            color.filled(),
+        );
+        bar.set_margin(0, 0, 3, 3);
+        bar
+    }))?;
+
+    chart.draw_series(bars.iter().enumerate().map(|(i, &(_, proportion, _))| {
+        Text::new(
+            format!("{:.1}", proportion),
+            (SegmentValue::CenterOf(i), proportion.min(Y_MAX)),
+            ("sans-serif", 14)
+                .into_font()
+                .color(&BLACK)
+                .pos(Pos::new(HPos::Center, VPos::Bottom)),
+        )
+    }))?;
+
+    if ratio <= Y_MAX {
+        let threshold: Vec<(SegmentValue<usize>, f64)> =
+            vec![(SegmentValue::Exact(0), ratio), (SegmentValue::Last, ratio)];
+        chart.draw_series(DashedLineSeries::new(
+            threshold,
+            8,
+            6,
+            BLACK.stroke_width(2),
+        ))?;
+    }
+
+    root.present()?;
+    Ok(())
+}